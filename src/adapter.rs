@@ -0,0 +1,329 @@
+#[derive(Copy, Clone, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Backend {
+    Vulkan,
+    // OpenGL / GLES, for machines without Vulkan drivers
+    Gl,
+    // Whatever wgpu finds on this platform
+    Any,
+}
+
+impl Backend {
+    pub fn backends(self) -> wgpu::Backends {
+        match self {
+            Backend::Vulkan => wgpu::Backends::VULKAN,
+            Backend::Gl => wgpu::Backends::GL,
+            Backend::Any => wgpu::Backends::all(),
+        }
+    }
+}
+
+// A specific adapter, by its position in the enumeration order or by part of its name
+#[derive(Clone, Debug)]
+pub enum AdapterSelector {
+    Index(usize),
+    Name(String),
+}
+
+impl AdapterSelector {
+    pub fn parse(spec: &str) -> AdapterSelector {
+        match spec.parse() {
+            Ok(index) => AdapterSelector::Index(index),
+            Err(_) => AdapterSelector::Name(spec.to_lowercase()),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AdapterOptions {
+    pub backend: Backend,
+    pub adapter: Option<AdapterSelector>,
+    // Only use a software adapter (llvmpipe, lavapipe, WARP)
+    pub force_fallback: bool,
+}
+
+pub fn create_instance(options: &AdapterOptions) -> wgpu::Instance {
+    wgpu::Instance::new(wgpu::InstanceDescriptor {
+        backends: options.backend.backends(),
+        dx12_shader_compiler: Default::default(),
+        flags: wgpu::InstanceFlags::from_build_config(), // bless wgpu for adding this feature
+        gles_minor_version: wgpu::Gles3MinorVersion::Automatic,
+    })
+}
+
+// winit 0.28 hands out raw-window-handle 0.5 handles and wgpu wants 0.6 ones, so they're rebuilt here.
+// The surface must be dropped before the window
+pub fn create_surface(
+    instance: &wgpu::Instance,
+    window: &winit::window::Window,
+) -> Result<wgpu::Surface<'static>, Box<dyn std::error::Error>> {
+    use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle};
+    use raw_window_handle_06 as rwh;
+    use std::num::{NonZeroIsize, NonZeroU32};
+    use std::ptr::NonNull;
+
+    let raw_display_handle = match window.raw_display_handle() {
+        raw_window_handle::RawDisplayHandle::Xlib(handle) => rwh::RawDisplayHandle::Xlib(
+            rwh::XlibDisplayHandle::new(NonNull::new(handle.display), handle.screen),
+        ),
+        raw_window_handle::RawDisplayHandle::Xcb(handle) => rwh::RawDisplayHandle::Xcb(
+            rwh::XcbDisplayHandle::new(NonNull::new(handle.connection), handle.screen),
+        ),
+        raw_window_handle::RawDisplayHandle::Wayland(handle) => {
+            rwh::RawDisplayHandle::Wayland(rwh::WaylandDisplayHandle::new(
+                NonNull::new(handle.display).ok_or("Null Wayland display")?,
+            ))
+        }
+        raw_window_handle::RawDisplayHandle::Windows(_) => {
+            rwh::RawDisplayHandle::Windows(rwh::WindowsDisplayHandle::new())
+        }
+        raw_window_handle::RawDisplayHandle::AppKit(_) => {
+            rwh::RawDisplayHandle::AppKit(rwh::AppKitDisplayHandle::new())
+        }
+        other => return Err(format!("Unsupported display {other:?}").into()),
+    };
+    let raw_window_handle = match window.raw_window_handle() {
+        raw_window_handle::RawWindowHandle::Xlib(handle) => {
+            let mut xlib = rwh::XlibWindowHandle::new(handle.window);
+            xlib.visual_id = handle.visual_id;
+            rwh::RawWindowHandle::Xlib(xlib)
+        }
+        raw_window_handle::RawWindowHandle::Xcb(handle) => {
+            let mut xcb =
+                rwh::XcbWindowHandle::new(NonZeroU32::new(handle.window).ok_or("Null X window")?);
+            xcb.visual_id = NonZeroU32::new(handle.visual_id);
+            rwh::RawWindowHandle::Xcb(xcb)
+        }
+        raw_window_handle::RawWindowHandle::Wayland(handle) => {
+            rwh::RawWindowHandle::Wayland(rwh::WaylandWindowHandle::new(
+                NonNull::new(handle.surface).ok_or("Null Wayland surface")?,
+            ))
+        }
+        raw_window_handle::RawWindowHandle::Win32(handle) => {
+            let mut win32 = rwh::Win32WindowHandle::new(
+                NonZeroIsize::new(handle.hwnd as isize).ok_or("Null window handle")?,
+            );
+            win32.hinstance = NonZeroIsize::new(handle.hinstance as isize);
+            rwh::RawWindowHandle::Win32(win32)
+        }
+        raw_window_handle::RawWindowHandle::AppKit(handle) => rwh::RawWindowHandle::AppKit(
+            rwh::AppKitWindowHandle::new(NonNull::new(handle.ns_view).ok_or("Null NSView")?),
+        ),
+        other => return Err(format!("Unsupported window {other:?}").into()),
+    };
+
+    Ok(unsafe {
+        instance.create_surface_unsafe(wgpu::SurfaceTargetUnsafe::RawHandle {
+            raw_display_handle,
+            raw_window_handle,
+        })
+    }?)
+}
+
+pub fn describe(info: &wgpu::AdapterInfo) -> String {
+    format!(
+        "{} ({:?}, {:?}, driver {})",
+        info.name, info.backend, info.device_type, info.driver
+    )
+}
+
+// Without a selector wgpu picks the high performance adapter. Adapters that can't present to the
+// surface are skipped when there is one
+pub async fn request_adapter(
+    instance: &wgpu::Instance,
+    options: &AdapterOptions,
+    surface: Option<&wgpu::Surface<'_>>,
+) -> Result<wgpu::Adapter, Box<dyn std::error::Error>> {
+    let Some(selector) = &options.adapter else {
+        return instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                compatible_surface: surface,
+                force_fallback_adapter: options.force_fallback,
+            })
+            .await
+            .ok_or_else(|| {
+                format!(
+                    "No {}adapter found for the {:?} backend",
+                    if options.force_fallback {
+                        "fallback "
+                    } else {
+                        ""
+                    },
+                    options.backend
+                )
+                .into()
+            });
+    };
+
+    let adapters = instance.enumerate_adapters(options.backend.backends());
+    let usable = |adapter: &wgpu::Adapter| {
+        let presentable = match surface {
+            Some(surface) => adapter.is_surface_supported(surface),
+            None => true,
+        };
+        presentable
+            && (!options.force_fallback || adapter.get_info().device_type == wgpu::DeviceType::Cpu)
+    };
+    let found = adapters.iter().enumerate().position(|(index, adapter)| {
+        usable(adapter)
+            && match selector {
+                AdapterSelector::Index(i) => index == *i,
+                AdapterSelector::Name(name) => {
+                    adapter.get_info().name.to_lowercase().contains(name)
+                }
+            }
+    });
+    match found {
+        Some(index) => Ok(adapters.into_iter().nth(index).unwrap()),
+        None => {
+            let available: Vec<String> = adapters
+                .iter()
+                .enumerate()
+                .map(|(index, adapter)| format!("{index}: {}", describe(&adapter.get_info())))
+                .collect();
+            Err(format!(
+                "No usable adapter matches {selector:?}. Available adapters:\n{}",
+                available.join("\n")
+            )
+            .into())
+        }
+    }
+}
+
+// What the renderer can do on an adapter, worked out from its features instead of demanding them all
+pub struct Capabilities {
+    pub features: wgpu::Features,
+    pub limits: wgpu::Limits,
+    // Format the downsampler writes. Switched to Rgba8Unorm for a window surface that doesn't offer it
+    pub output_format: wgpu::TextureFormat,
+    // Lets filling stop dispatching once it converges
+    pub indirect_dispatch: bool,
+    pub timestamps: bool,
+    // Lets the compute rasterizer resolve visibility in one pass
+    pub int64_atomics: bool,
+    // What got turned off or swapped for a slower path, for reporting
+    pub degraded: Vec<&'static str>,
+}
+
+impl Capabilities {
+    pub fn probe(adapter: &wgpu::Adapter) -> Result<Capabilities, Box<dyn std::error::Error>> {
+        // The final resolve is a compute pass, so there's nothing to fall back to without them
+        if !adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS)
+        {
+            return Err(format!(
+                "{} doesn't support compute shaders",
+                adapter.get_info().name
+            )
+            .into());
+        }
+        let available = adapter.features();
+        let mut features = wgpu::Features::empty();
+        let mut degraded = Vec::new();
+
+        let output_format = if available.contains(wgpu::Features::BGRA8UNORM_STORAGE) {
+            features |= wgpu::Features::BGRA8UNORM_STORAGE;
+            wgpu::TextureFormat::Bgra8Unorm
+        } else {
+            degraded.push("no BGRA8 storage textures: output is Rgba8Unorm, so a window surface has to offer Rgba8Unorm");
+            wgpu::TextureFormat::Rgba8Unorm
+        };
+
+        let indirect_dispatch = adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(wgpu::DownlevelFlags::INDIRECT_EXECUTION);
+        if !indirect_dispatch {
+            degraded.push("no indirect dispatch: hole filling always runs up to its iteration cap");
+        }
+
+        // The profiler writes its timestamps between passes, straight on the encoder
+        let timestamp_features =
+            wgpu::Features::TIMESTAMP_QUERY | wgpu::Features::TIMESTAMP_QUERY_INSIDE_ENCODERS;
+        let timestamps = available.contains(timestamp_features);
+        if timestamps {
+            features |= timestamp_features;
+        } else {
+            degraded.push("no timestamp queries: profiling is unavailable");
+        }
+
+        // Depth and point index packed into one u64 key decide visibility with a single atomicMin
+        let int64_features =
+            wgpu::Features::SHADER_INT64 | wgpu::Features::SHADER_INT64_ATOMIC_MIN_MAX;
+        let int64_atomics = available.contains(int64_features);
+        if int64_atomics {
+            features |= int64_features;
+        } else {
+            degraded.push(
+                "no 64-bit atomics: the compute rasterizer takes separate depth and index passes",
+            );
+        }
+
+        Ok(Capabilities {
+            features,
+            // The compute rasterizer binds the whole vertex buffer as storage, so ask for everything the
+            // adapter allows rather than the portable defaults
+            limits: adapter.limits(),
+            output_format,
+            indirect_dispatch,
+            timestamps,
+            int64_atomics,
+            degraded,
+        })
+    }
+}
+
+// Everything --adapter-info prints: each adapter the backend can see and how the renderer would run on it
+pub fn print_adapter_info(options: &AdapterOptions) {
+    let instance = create_instance(options);
+    for (index, adapter) in instance
+        .enumerate_adapters(options.backend.backends())
+        .into_iter()
+        .enumerate()
+    {
+        let info = adapter.get_info();
+        println!("{index}: {}", describe(&info));
+        println!(
+            "    vendor {:#06x}, device {:#06x}",
+            info.vendor, info.device
+        );
+        let limits = adapter.limits();
+        println!(
+            "    max texture size {}, max storage buffer binding {} bytes",
+            limits.max_texture_dimension_2d, limits.max_storage_buffer_binding_size
+        );
+        match Capabilities::probe(&adapter) {
+            Ok(capabilities) if capabilities.degraded.is_empty() => {
+                println!("    fully supported")
+            }
+            Ok(capabilities) => {
+                for degraded in capabilities.degraded {
+                    println!("    {degraded}");
+                }
+            }
+            Err(e) => println!("    unusable: {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_selectors() {
+        assert!(matches!(
+            AdapterSelector::parse("1"),
+            AdapterSelector::Index(1)
+        ));
+        assert!(
+            matches!(AdapterSelector::parse("Intel"), AdapterSelector::Name(name) if name == "intel")
+        );
+        // Anything that isn't an index is matched against names
+        assert!(
+            matches!(AdapterSelector::parse("-1"), AdapterSelector::Name(name) if name == "-1")
+        );
+    }
+}
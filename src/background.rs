@@ -0,0 +1,60 @@
+use image::{io::Reader as ImageReader, ImageBuffer, Rgba};
+
+// What shows through wherever no point landed and filling didn't reach. Until the downsampler
+// composites it, such holes are the pixels with an alpha of 1, the inverse of the usual meaning
+#[derive(Clone)]
+pub enum Background {
+    // sRGB color, each channel 0..1
+    Color([f32; 3]),
+    // Image stretched over the whole output
+    Backdrop(ImageBuffer<Rgba<u8>, Vec<u8>>),
+    // Holes get an alpha of 0 in screenshots
+    Transparent,
+}
+
+impl Background {
+    // "transparent", a hex color like "#1e1e1e", or the path of a backdrop image
+    pub fn parse(spec: &str) -> Result<Background, Box<dyn std::error::Error>> {
+        if spec == "transparent" {
+            return Ok(Background::Transparent);
+        }
+        if let Some(hex) = spec.strip_prefix('#') {
+            if hex.len() != 6 || !hex.is_ascii() {
+                return Err(format!("Expected a color like #rrggbb, got {spec}").into());
+            }
+            let channel = |i: usize| -> Result<f32, std::num::ParseIntError> {
+                Ok(u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)? as f32 / 255.0)
+            };
+            return Ok(Background::Color([channel(0)?, channel(1)?, channel(2)?]));
+        }
+        Ok(Background::Backdrop(
+            ImageReader::open(spec)?.decode()?.to_rgba8(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_specs() {
+        assert!(matches!(
+            Background::parse("transparent").unwrap(),
+            Background::Transparent
+        ));
+        let Background::Color(color) = Background::parse("#1e80FF").unwrap() else {
+            panic!("expected a color");
+        };
+        assert_eq!(color, [30.0 / 255.0, 128.0 / 255.0, 1.0]);
+    }
+
+    #[test]
+    fn rejects_bad_colors() {
+        // The last one is 6 bytes, but not 6 hex digits
+        for spec in ["#1e1e1", "#1e1e1e1", "#1e1e1g", "#a\u{20ac}bc"] {
+            assert!(Background::parse(spec).is_err(), "{spec}");
+        }
+        assert!(Background::parse("no-such-backdrop.png").is_err());
+    }
+}
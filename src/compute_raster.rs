@@ -0,0 +1,345 @@
+use crate::layer::Layer;
+use crate::renderer::{DEPTH_STORAGE_FORMAT, WORKING_FORMAT};
+use crate::texture::Texture;
+
+// How points get into the target textures before filling
+#[derive(Copy, Clone, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum RasterMode {
+    // Point-list render pass with a depth attachment
+    Hardware,
+    // Compute passes resolving visibility with atomics, which holds up better with dense clouds
+    Compute,
+}
+
+const POINT_WORKGROUP_SIZE: u32 = 256;
+
+// Splats every point into its pixel with storage buffer atomics and resolves the winners into the same
+// color and depth targets the hardware raster pass writes. Layers run each point pass in turn, with points
+// numbered across all of them, and each layer resolves the pixels it won
+pub struct ComputeRasterizer {
+    visibility: Visibility,
+    // Per-pixel buffers with the binding the clear and resolve passes see them at
+    pixel_buffers: Vec<(u32, wgpu::Buffer)>,
+    bind_group_layout: wgpu::BindGroupLayout,
+    clear_pipeline: wgpu::ComputePipeline,
+    resolve_empty_pipeline: wgpu::ComputePipeline,
+    resolve_pipeline: wgpu::ComputePipeline,
+}
+
+// How the nearest point at each pixel is found
+enum Visibility {
+    // Depth and point index packed into one u64 key and resolved with a single atomicMin pass. Needs
+    // 64-bit atomics, and binds the key buffer as atomics in a bind group of its own
+    Packed {
+        bind_group_layout: wgpu::BindGroupLayout,
+        splat_pipeline: wgpu::ComputePipeline,
+    },
+    // The 32-bit fallback: a depth atomicMin pass, then an index atomicMin pass over the points that
+    // matched the winning depth
+    TwoPass {
+        depth_pipeline: wgpu::ComputePipeline,
+        index_pipeline: wgpu::ComputePipeline,
+    },
+}
+
+impl ComputeRasterizer {
+    // int64_atomics is Capabilities::int64_atomics, and picks the single pass
+    pub fn new(device: &wgpu::Device, dims: (u32, u32), int64_atomics: bool) -> Self {
+        let pixel_buffer = |label| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: dims.0 as u64 * dims.1 as u64 * Self::pixel_bytes(int64_atomics),
+                usage: wgpu::BufferUsages::STORAGE,
+                mapped_at_creation: false,
+            })
+        };
+        let pixel_buffers = if int64_atomics {
+            vec![(3, pixel_buffer("Compute Raster Keys"))]
+        } else {
+            vec![
+                (2, pixel_buffer("Compute Raster Depth")),
+                (3, pixel_buffer("Compute Raster Index")),
+            ]
+        };
+
+        let buffer_entry = |binding, ty| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let storage_texture_entry = |binding, format| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::StorageTexture {
+                access: wgpu::StorageTextureAccess::WriteOnly,
+                format,
+                view_dimension: wgpu::TextureViewDimension::D2,
+            },
+            count: None,
+        };
+        let layer_entries = [
+            buffer_entry(0, wgpu::BufferBindingType::Uniform),
+            buffer_entry(1, wgpu::BufferBindingType::Storage { read_only: true }),
+        ];
+        let entries: Vec<wgpu::BindGroupLayoutEntry> = layer_entries
+            .into_iter()
+            .chain(pixel_buffers.iter().map(|(binding, _)| {
+                buffer_entry(
+                    *binding,
+                    wgpu::BufferBindingType::Storage { read_only: false },
+                )
+            }))
+            .chain([
+                storage_texture_entry(4, WORKING_FORMAT),
+                storage_texture_entry(5, DEPTH_STORAGE_FORMAT),
+            ])
+            .collect();
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Compute Raster Bindgroup Layout"),
+            entries: &entries,
+        });
+
+        let shader = device.create_shader_module(if int64_atomics {
+            wgpu::include_wgsl!("shaders/compute_raster_int64.wgsl")
+        } else {
+            wgpu::include_wgsl!("shaders/compute_raster.wgsl")
+        });
+        let pipeline = |entry_point, bind_group_layout: &wgpu::BindGroupLayout| {
+            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("compute_raster_layout"),
+                bind_group_layouts: &[bind_group_layout],
+                push_constant_ranges: &[],
+            });
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                module: &shader,
+                layout: Some(&layout),
+                entry_point,
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        };
+
+        let visibility = if int64_atomics {
+            let splat_bind_group_layout =
+                device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    label: Some("Compute Raster Splat Bindgroup Layout"),
+                    entries: &[
+                        buffer_entry(0, wgpu::BufferBindingType::Uniform),
+                        buffer_entry(1, wgpu::BufferBindingType::Storage { read_only: true }),
+                        buffer_entry(2, wgpu::BufferBindingType::Storage { read_only: false }),
+                    ],
+                });
+            Visibility::Packed {
+                splat_pipeline: pipeline("splat", &splat_bind_group_layout),
+                bind_group_layout: splat_bind_group_layout,
+            }
+        } else {
+            Visibility::TwoPass {
+                depth_pipeline: pipeline("depth", &bind_group_layout),
+                index_pipeline: pipeline("index", &bind_group_layout),
+            }
+        };
+
+        ComputeRasterizer {
+            visibility,
+            pixel_buffers,
+            clear_pipeline: pipeline("clear", &bind_group_layout),
+            resolve_empty_pipeline: pipeline("resolve_empty", &bind_group_layout),
+            resolve_pipeline: pipeline("resolve", &bind_group_layout),
+            bind_group_layout,
+        }
+    }
+
+    // Bytes per pixel in each per-pixel buffer: a u64 key, or a u32 depth and a u32 index
+    fn pixel_bytes(int64_atomics: bool) -> u64 {
+        if int64_atomics {
+            8
+        } else {
+            4
+        }
+    }
+
+    // Every vertex buffer and the per-pixel buffers are bound whole as storage buffers, so each has to fit
+    // the adapter's storage buffer binding limit
+    pub fn check_limits(
+        limits: &wgpu::Limits,
+        dims: (u32, u32),
+        layers: &[Layer],
+        int64_atomics: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let max = limits.max_storage_buffer_binding_size as u64;
+        let pixel_bytes = dims.0 as u64 * dims.1 as u64 * Self::pixel_bytes(int64_atomics);
+        if pixel_bytes > max {
+            return Err(format!(
+                "{}x{} needs {pixel_bytes} byte pixel buffers but storage buffers are limited to {max} bytes",
+                dims.0, dims.1
+            )
+            .into());
+        }
+        if let Some(layer) = layers.iter().find(|layer| layer.vertex_buffer.size() > max) {
+            return Err(format!(
+                "Layer {} has {} bytes of points but storage buffers are limited to {max} bytes",
+                layer.name,
+                layer.vertex_buffer.size()
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    // Vertex buffers are read as raw floats, so they need STORAGE usage. Output textures must match the
+    // size the rasterizer was created with. The first layer is always bound for the per-pixel passes, so
+    // there has to be one even if nothing is visible
+    pub fn run(
+        &self,
+        device: &wgpu::Device,
+        command_encoder: &mut wgpu::CommandEncoder,
+        layers: &[Layer],
+        outputs: (&Texture, &Texture),
+    ) {
+        let bind_groups: Vec<wgpu::BindGroup> = layers
+            .iter()
+            .map(|layer| self.create_bind_group(device, layer, outputs))
+            .collect();
+        let (output_image, _) = outputs;
+        let dims = (output_image.texture.width(), output_image.texture.height());
+        let pixel_groups = (dims.0.div_ceil(8), dims.1.div_ceil(8), 1);
+        // Spill into y once there are more workgroups than one dimension allows
+        let max_groups = device.limits().max_compute_workgroups_per_dimension;
+        let point_groups = |layer: &Layer| {
+            let groups = layer.point_count().div_ceil(POINT_WORKGROUP_SIZE);
+            (groups.clamp(1, max_groups), groups.div_ceil(max_groups), 1)
+        };
+        let visible: Vec<usize> = (0..layers.len())
+            .filter(|&index| layers[index].visible)
+            .collect();
+        // Only the single pass binds the key buffer as atomics
+        let splat_bind_groups: Vec<wgpu::BindGroup> = match &self.visibility {
+            Visibility::Packed {
+                bind_group_layout, ..
+            } => layers
+                .iter()
+                .map(|layer| self.create_splat_bind_group(device, bind_group_layout, layer))
+                .collect(),
+            Visibility::TwoPass { .. } => Vec::new(),
+        };
+
+        let mut compute_pass = command_encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("compute_raster_pass"),
+            ..Default::default()
+        });
+        let mut dispatch = |pipeline, bind_group, (x, y, z)| {
+            compute_pass.set_pipeline(pipeline);
+            compute_pass.set_bind_group(0, bind_group, &[]);
+            compute_pass.dispatch_workgroups(x, y, z);
+        };
+        dispatch(&self.clear_pipeline, &bind_groups[0], pixel_groups);
+        match &self.visibility {
+            Visibility::Packed { splat_pipeline, .. } => {
+                for &index in &visible {
+                    dispatch(
+                        splat_pipeline,
+                        &splat_bind_groups[index],
+                        point_groups(&layers[index]),
+                    );
+                }
+            }
+            Visibility::TwoPass {
+                depth_pipeline,
+                index_pipeline,
+            } => {
+                for &index in &visible {
+                    dispatch(
+                        depth_pipeline,
+                        &bind_groups[index],
+                        point_groups(&layers[index]),
+                    );
+                }
+                for &index in &visible {
+                    dispatch(
+                        index_pipeline,
+                        &bind_groups[index],
+                        point_groups(&layers[index]),
+                    );
+                }
+            }
+        }
+        dispatch(&self.resolve_empty_pipeline, &bind_groups[0], pixel_groups);
+        for &index in &visible {
+            dispatch(&self.resolve_pipeline, &bind_groups[index], pixel_groups);
+        }
+    }
+
+    fn create_bind_group(
+        &self,
+        device: &wgpu::Device,
+        layer: &Layer,
+        (output_image, output_depth): (&Texture, &Texture),
+    ) -> wgpu::BindGroup {
+        let mut entries = vec![
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: layer.uniform_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: layer.vertex_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::TextureView(&output_image.texture_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: wgpu::BindingResource::TextureView(&output_depth.texture_view),
+            },
+        ];
+        entries.extend(
+            self.pixel_buffers
+                .iter()
+                .map(|(binding, buffer)| wgpu::BindGroupEntry {
+                    binding: *binding,
+                    resource: buffer.as_entire_binding(),
+                }),
+        );
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Compute Raster Bindgroup"),
+            layout: &self.bind_group_layout,
+            entries: &entries,
+        })
+    }
+
+    // The layer's buffers with the key buffer as atomics, for the splat pass
+    fn create_splat_bind_group(
+        &self,
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        layer: &Layer,
+    ) -> wgpu::BindGroup {
+        let (_, key_buffer) = &self.pixel_buffers[0];
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Compute Raster Splat Bindgroup"),
+            layout: bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: layer.uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: layer.vertex_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: key_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+}
@@ -0,0 +1,236 @@
+use bytemuck::Zeroable;
+use wgpu::util::DeviceExt;
+
+use crate::renderer::WORKING_FORMAT;
+use crate::texture::Texture;
+use crate::view_params::ViewParams;
+
+// What the viewer shows. Stage modes show that stage's color as it's handed to the next one (a stage that's
+// off passes the previous one's through), the rest visualize the final depth
+#[derive(Copy, Clone, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum DisplayMode {
+    Color,
+    // View depth stretched over the nearest to furthest point in the frame, near is red/yellow
+    DepthTurbo,
+    DepthViridis,
+    // Black for points, white for holes filled in, red for holes left empty
+    Holes,
+    Normals,
+    Raster,
+    BackgroundFilling,
+    OcclusionFilling,
+}
+
+impl DisplayMode {
+    pub fn next(self) -> DisplayMode {
+        let modes = <DisplayMode as clap::ValueEnum>::value_variants();
+        let index = modes.iter().position(|mode| *mode == self).unwrap();
+        modes[(index + 1) % modes.len()]
+    }
+
+    // Compute entry point for the modes DebugView draws
+    fn entry_point(&self) -> Option<&'static str> {
+        match self {
+            DisplayMode::DepthTurbo => Some("depth_turbo"),
+            DisplayMode::DepthViridis => Some("depth_viridis"),
+            DisplayMode::Holes => Some("holes"),
+            DisplayMode::Normals => Some("normals"),
+            DisplayMode::Color
+            | DisplayMode::Raster
+            | DisplayMode::BackgroundFilling
+            | DisplayMode::OcclusionFilling => None,
+        }
+    }
+
+    pub fn needs_debug_view(&self) -> bool {
+        self.entry_point().is_some()
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct Params {
+    inverse_projection: [f32; 16],
+}
+
+// Draws the depth, hole and normal views from the depth targets
+pub struct DebugView {
+    pub texture: Texture,
+    params_buffer: wgpu::Buffer,
+    depth_range: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    depth_range_pipeline: wgpu::ComputePipeline,
+    pipelines: Vec<(DisplayMode, wgpu::ComputePipeline)>,
+}
+
+impl DebugView {
+    pub fn new(device: &wgpu::Device, dims: (u32, u32)) -> Self {
+        let texture = Texture::new(
+            device,
+            dims,
+            WORKING_FORMAT,
+            wgpu::TextureUsages::STORAGE_BINDING
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
+            "debug_view",
+        );
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Debug View Params"),
+            contents: bytemuck::cast_slice(&[Params::zeroed()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let depth_range = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Debug View Depth Range"),
+            size: 2 * std::mem::size_of::<u32>() as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let buffer_entry = |binding, ty| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let input_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Debug View Bindgroup Layout"),
+            entries: &[
+                buffer_entry(0, wgpu::BufferBindingType::Uniform),
+                input_entry(1),
+                input_entry(2),
+                buffer_entry(3, wgpu::BufferBindingType::Storage { read_only: false }),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: WORKING_FORMAT,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let shader = device.create_shader_module(wgpu::include_wgsl!("shaders/debug_view.wgsl"));
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("debug_view_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = |entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                module: &shader,
+                layout: Some(&layout),
+                entry_point,
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        };
+        let pipelines = <DisplayMode as clap::ValueEnum>::value_variants()
+            .iter()
+            .filter_map(|mode| Some((*mode, pipeline(mode.entry_point()?))))
+            .collect();
+
+        DebugView {
+            texture,
+            params_buffer,
+            depth_range,
+            bind_group_layout,
+            depth_range_pipeline: pipeline("depth_range_pass"),
+            pipelines,
+        }
+    }
+
+    // view_params must be the view the depth targets were rendered with, aspect fitting included
+    pub fn write(&self, queue: &wgpu::Queue, view_params: &ViewParams) {
+        let inverse_projection = view_params
+            .wgpu_projection()
+            .try_inverse()
+            .unwrap_or_else(nalgebra::Matrix4::identity);
+        let params = Params {
+            inverse_projection: inverse_projection.as_slice().try_into().unwrap(),
+        };
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::cast_slice(&[params]));
+    }
+
+    // Draw mode into self.texture from depth (the stage being shown) and raster_depth (before filling).
+    // Does nothing for modes that don't need a debug view
+    pub fn run(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        command_encoder: &mut wgpu::CommandEncoder,
+        mode: DisplayMode,
+        depth: &Texture,
+        raster_depth: &Texture,
+    ) {
+        let Some((_, pipeline)) = self
+            .pipelines
+            .iter()
+            .find(|(pipeline_mode, _)| *pipeline_mode == mode)
+        else {
+            return;
+        };
+        // Emptied before the frame is submitted, so the range pass can narrow it down
+        queue.write_buffer(
+            &self.depth_range,
+            0,
+            bytemuck::cast_slice(&[u32::MAX, 0u32]),
+        );
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Debug View Bindgroup"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&depth.texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&raster_depth.texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: self.depth_range.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&self.texture.texture_view),
+                },
+            ],
+        });
+        let dims = (self.texture.texture.width(), self.texture.texture.height());
+        let groups = (dims.0.div_ceil(8), dims.1.div_ceil(8), 1);
+        let mut compute_pass = command_encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("debug_view_pass"),
+            ..Default::default()
+        });
+        compute_pass.set_bind_group(0, &bind_group, &[]);
+        if matches!(mode, DisplayMode::DepthTurbo | DisplayMode::DepthViridis) {
+            compute_pass.set_pipeline(&self.depth_range_pipeline);
+            compute_pass.dispatch_workgroups(groups.0, groups.1, groups.2);
+        }
+        compute_pass.set_pipeline(pipeline);
+        compute_pass.dispatch_workgroups(groups.0, groups.1, groups.2);
+    }
+}
@@ -0,0 +1,176 @@
+use bytemuck::Zeroable;
+use wgpu::util::DeviceExt;
+
+use crate::renderer::WORKING_FORMAT;
+use crate::texture::Texture;
+use crate::view_params::ViewParams;
+
+// Aperture is the blur radius, in output pixels, of points infinitely far behind the focus plane. Points
+// in front of it blur faster the closer they get, up to max_radius. The focus plane itself belongs to the
+// view (see ViewParams::focus_distance), so it moves with camera paths and saved views
+#[derive(Copy, Clone, Debug)]
+pub struct DepthOfField {
+    pub aperture: f32,
+    pub max_radius: f32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct Params {
+    inverse_projection: [f32; 16],
+    focus: f32,
+    aperture: f32,
+    max_radius: f32,
+    _padding: f32,
+}
+
+// Blurs the filled color by each pixel's circle of confusion, worked out from the depth target
+pub struct DepthOfFieldPass {
+    pub texture: Texture,
+    params_buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::ComputePipeline,
+}
+
+impl DepthOfFieldPass {
+    pub fn new(device: &wgpu::Device, dims: (u32, u32)) -> Self {
+        // Read by the downsampler and copied into the stereo eye textures
+        let texture = Texture::new(
+            device,
+            dims,
+            WORKING_FORMAT,
+            wgpu::TextureUsages::STORAGE_BINDING
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
+            "depth_of_field",
+        );
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Depth of Field Params"),
+            contents: bytemuck::cast_slice(&[Params::zeroed()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let input_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Depth of Field Bindgroup Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                input_entry(1),
+                input_entry(2),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: WORKING_FORMAT,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let shader =
+            device.create_shader_module(wgpu::include_wgsl!("shaders/depth_of_field.wgsl"));
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("depth_of_field_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("depth_of_field"),
+            module: &shader,
+            layout: Some(&layout),
+            entry_point: "main",
+            compilation_options: Default::default(),
+            cache: None,
+        });
+
+        DepthOfFieldPass {
+            texture,
+            params_buffer,
+            bind_group_layout,
+            pipeline,
+        }
+    }
+
+    // view_params must be the view the depth target was rendered with, aspect fitting included. scale
+    // converts output pixels to target pixels when supersampling
+    pub fn write(
+        &self,
+        queue: &wgpu::Queue,
+        view_params: &ViewParams,
+        settings: &DepthOfField,
+        scale: f32,
+    ) {
+        let inverse_projection = view_params
+            .wgpu_projection()
+            .try_inverse()
+            .unwrap_or_else(nalgebra::Matrix4::identity);
+        let params = Params {
+            inverse_projection: inverse_projection.as_slice().try_into().unwrap(),
+            focus: view_params.focus_distance(),
+            aperture: settings.aperture * scale,
+            max_radius: settings.max_radius * scale,
+            _padding: 0.0,
+        };
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::cast_slice(&[params]));
+    }
+
+    // Blur input (color and depth) into self.texture, which must be the same size
+    pub fn run(
+        &self,
+        device: &wgpu::Device,
+        command_encoder: &mut wgpu::CommandEncoder,
+        (input_image, input_depth): (&Texture, &Texture),
+    ) {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Depth of Field Bindgroup"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&input_image.texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&input_depth.texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&self.texture.texture_view),
+                },
+            ],
+        });
+        let dims = (self.texture.texture.width(), self.texture.texture.height());
+        let mut compute_pass = command_encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("depth_of_field_pass"),
+            ..Default::default()
+        });
+        compute_pass.set_pipeline(&self.pipeline);
+        compute_pass.set_bind_group(0, &bind_group, &[]);
+        compute_pass.dispatch_workgroups(dims.0.div_ceil(8), dims.1.div_ceil(8), 1);
+    }
+}
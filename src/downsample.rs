@@ -0,0 +1,289 @@
+use wgpu::util::DeviceExt;
+
+use crate::background::Background;
+use crate::renderer::{srgb_to_linear, DEPTH_STORAGE_FORMAT};
+use crate::texture::Texture;
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct BackgroundUniform {
+    // Linear color
+    color: [f32; 4],
+    // 0: color, 1: backdrop, 2: transparent
+    mode: u32,
+    _padding: [u32; 3],
+}
+
+// Resolves the supersampled linear render into output-sized sRGB color and depth textures, putting the
+// background behind any remaining holes. With a factor of 1 it only does the background and sRGB encoding.
+// Like every compute pass here, its inputs are sampled rather than storage textures, since reading Rgba16Float
+// and R32Float storage textures is an adapter specific feature
+pub struct Downsampler {
+    pub texture: Texture,
+    // Bgra8Unorm where the adapter can write it from a shader, Rgba8Unorm otherwise. Either way it holds sRGB
+    // encoded bytes, since storage textures can't be sRGB
+    format: wgpu::TextureFormat,
+    pub depth: Texture,
+    background_buffer: wgpu::Buffer,
+    // A 1x1 placeholder unless the background is a backdrop image
+    backdrop: Texture,
+    backdrop_sampler: wgpu::Sampler,
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::ComputePipeline,
+}
+
+impl Downsampler {
+    pub fn new(device: &wgpu::Device, dims: (u32, u32), format: wgpu::TextureFormat) -> Self {
+        let (texture, depth) = Downsampler::create_textures(device, dims, format);
+        let background_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Background"),
+            contents: bytemuck::cast_slice(&[BackgroundUniform::from(&Background::Color([
+                0.0, 0.0, 0.0,
+            ]))]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let backdrop = Downsampler::create_backdrop(device, (1, 1));
+        let backdrop_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Backdrop Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let input_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let storage_texture_entry = |binding, access, format| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::StorageTexture {
+                access,
+                format,
+                view_dimension: wgpu::TextureViewDimension::D2,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Downsample Bindgroup Layout"),
+            entries: &[
+                input_entry(0),
+                storage_texture_entry(1, wgpu::StorageTextureAccess::WriteOnly, format),
+                input_entry(2),
+                storage_texture_entry(
+                    3,
+                    wgpu::StorageTextureAccess::WriteOnly,
+                    DEPTH_STORAGE_FORMAT,
+                ),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 6,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        // The output's storage format is spelled out in the shader
+        let source = include_str!("shaders/downsample.wgsl");
+        let source = match format {
+            wgpu::TextureFormat::Rgba8Unorm => source.replace("bgra8unorm", "rgba8unorm"),
+            _ => source.to_string(),
+        };
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("downsample.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("downsample_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("downsample"),
+            module: &shader,
+            layout: Some(&layout),
+            entry_point: "main",
+            compilation_options: Default::default(),
+            cache: None,
+        });
+
+        Downsampler {
+            texture,
+            format,
+            depth,
+            background_buffer,
+            backdrop,
+            backdrop_sampler,
+            bind_group_layout,
+            pipeline,
+        }
+    }
+
+    fn create_textures(
+        device: &wgpu::Device,
+        dims: (u32, u32),
+        format: wgpu::TextureFormat,
+    ) -> (Texture, Texture) {
+        let usage = wgpu::TextureUsages::STORAGE_BINDING
+            | wgpu::TextureUsages::COPY_SRC
+            | wgpu::TextureUsages::COPY_DST;
+        (
+            Texture::new(device, dims, format, usage, "Output Buffer"),
+            Texture::new(
+                device,
+                dims,
+                DEPTH_STORAGE_FORMAT,
+                usage,
+                "Output Depth Buffer",
+            ),
+        )
+    }
+
+    fn create_backdrop(device: &wgpu::Device, dims: (u32, u32)) -> Texture {
+        // Sampling an sRGB texture gives linear color
+        Texture::new(
+            device,
+            dims,
+            wgpu::TextureFormat::Rgba8UnormSrgb,
+            wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            "Backdrop",
+        )
+    }
+
+    pub fn set_background(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        background: &Background,
+    ) {
+        queue.write_buffer(
+            &self.background_buffer,
+            0,
+            bytemuck::cast_slice(&[BackgroundUniform::from(background)]),
+        );
+        if let Background::Backdrop(image) = background {
+            self.backdrop = Downsampler::create_backdrop(device, image.dimensions());
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &self.backdrop.texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                image,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(4 * image.width()),
+                    rows_per_image: None,
+                },
+                wgpu::Extent3d {
+                    width: image.width(),
+                    height: image.height(),
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, dims: (u32, u32)) {
+        (self.texture, self.depth) = Downsampler::create_textures(device, dims, self.format);
+    }
+
+    pub fn run(
+        &self,
+        device: &wgpu::Device,
+        command_encoder: &mut wgpu::CommandEncoder,
+        input: &Texture,
+        input_depth: &Texture,
+    ) {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Downsample Bindgroup"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&input.texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&self.texture.texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&input_depth.texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&self.depth.texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: self.background_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(&self.backdrop.texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::Sampler(&self.backdrop_sampler),
+                },
+            ],
+        });
+        let dims = (self.texture.texture.width(), self.texture.texture.height());
+        let mut compute_pass = command_encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("downsample_pass"),
+            ..Default::default()
+        });
+        compute_pass.set_pipeline(&self.pipeline);
+        compute_pass.set_bind_group(0, &bind_group, &[]);
+        compute_pass.dispatch_workgroups(dims.0.div_ceil(8), dims.1.div_ceil(8), 1);
+    }
+}
+
+impl From<&Background> for BackgroundUniform {
+    fn from(background: &Background) -> BackgroundUniform {
+        let (color, mode) = match background {
+            Background::Color(color) => (*color, 0),
+            Background::Backdrop(_) => ([0.0; 3], 1),
+            Background::Transparent => ([0.0; 3], 2),
+        };
+        BackgroundUniform {
+            color: [
+                srgb_to_linear(color[0]),
+                srgb_to_linear(color[1]),
+                srgb_to_linear(color[2]),
+                1.0,
+            ],
+            mode,
+            _padding: [0; 3],
+        }
+    }
+}
@@ -0,0 +1,34 @@
+use std::f32::consts::{FRAC_PI_2, PI};
+
+use nalgebra::Vector3;
+
+// How the input image maps pixels to rays
+#[derive(Copy, Clone, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum InputProjection {
+    // Ordinary image, unprojected onto the -1..1 square with depth along z
+    Planar,
+    // 360° panorama, unprojected onto a sphere around the origin with depth as the radius
+    Equirect,
+}
+
+// Depth 0 and 255 map to these distances from the center of the panorama
+pub const MIN_RADIUS: f32 = 0.5;
+pub const MAX_RADIUS: f32 = 2.5;
+
+// Unit ray through the center of pixel (x, y) of an image covering longitude_span (2π for a full panorama)
+// and all latitudes. The middle of the image looks down -z with +y up, longitude increases to the right
+pub fn direction(x: u32, y: u32, (width, height): (u32, u32), longitude_span: f32) -> Vector3<f32> {
+    let longitude = ((x as f32 + 0.5) / width as f32 - 0.5) * longitude_span;
+    let latitude = FRAC_PI_2 - ((y as f32 + 0.5) / height as f32) * PI;
+    Vector3::new(
+        latitude.cos() * longitude.sin(),
+        latitude.sin(),
+        -latitude.cos() * longitude.cos(),
+    )
+}
+
+// Position of a pixel given its depth normalized to 0..1
+pub fn unproject(x: u32, y: u32, dims: (u32, u32), depth: f32) -> [f32; 3] {
+    let radius = MIN_RADIUS + depth * (MAX_RADIUS - MIN_RADIUS);
+    (direction(x, y, dims, 2.0 * PI) * radius).into()
+}
@@ -1,29 +1,176 @@
+use serde::Serialize;
 use wgpu::util::DeviceExt;
 
+use crate::profiler::Profiler;
 use crate::texture::Texture;
+
+// How far a filling shader got in the last frame
+#[derive(Clone, Debug, Serialize)]
+pub struct FillingStats {
+    pub stage: &'static str,
+    pub iterations: u32,
+    // Pixels the last iteration changed, 0 once filling converged
+    pub count: u32,
+    // Pixels left empty, including any filling can't reach such as letterboxing
+    pub empty: u32,
+}
+
+// Runs a filling shader until an iteration changes nothing or the iteration cap is hit. The shader counts
+// changed and still empty pixels into the convergence tracker, and a one-invocation pass after every
+// iteration records them and zeroes the next iteration's indirect dispatch once nothing changed
 pub struct FillingShader {
+    // Used for profiler stage names
+    name: &'static str,
     pub textures: [(Texture, Texture); 2],
     convergence_tracker: wgpu::Buffer,
+    // Workgroup counts of the next iteration, reset from initial_dispatch at the start of every run
+    dispatch_args: wgpu::Buffer,
+    initial_dispatch: wgpu::Buffer,
+    // Iterations run and the last one's counts, copied into readback at the end of every run
+    status: wgpu::Buffer,
+    readback: wgpu::Buffer,
+    // Without indirect dispatch every iteration up to the cap runs, converged or not
+    indirect: bool,
+    bind_group_layout: wgpu::BindGroupLayout,
     bindgroups: [wgpu::BindGroup; 2],
     compute_pipeline: wgpu::ComputePipeline,
+    convergence_bind_group: wgpu::BindGroup,
+    convergence_pipeline: wgpu::ComputePipeline,
 }
 
+const STATUS_SIZE: u64 = 3 * std::mem::size_of::<u32>() as u64;
+
 impl FillingShader {
     pub fn new(
         device: &wgpu::Device,
         dims: (u32, u32),
         shader: wgpu::ShaderModuleDescriptor,
+        name: &'static str,
+        indirect: bool,
     ) -> Self {
+        let textures = FillingShader::create_textures(device, dims);
+
+        let buffer = |label, size, usage| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size,
+                usage,
+                mapped_at_creation: false,
+            })
+        };
+        // Changed pixels, then empty pixels
+        let convergence_tracker = buffer(
+            "Convergence Tracker",
+            2 * std::mem::size_of::<u32>() as u64,
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        );
+        let dispatch_args = buffer(
+            "Filling Dispatch",
+            STATUS_SIZE,
+            wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::INDIRECT
+                | wgpu::BufferUsages::COPY_DST,
+        );
+        let status = buffer(
+            "Filling Status",
+            STATUS_SIZE,
+            wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_SRC
+                | wgpu::BufferUsages::COPY_DST,
+        );
+        let readback = buffer(
+            "Filling Status Readback",
+            STATUS_SIZE,
+            wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        );
+        let (bind_group_layout, compute_pipeline) =
+            FillingShader::create_compute_pipeline(device, shader);
+        let bindgroups = FillingShader::create_bind_groups(
+            device,
+            &bind_group_layout,
+            &textures,
+            &convergence_tracker,
+        );
+
+        let convergence_shader =
+            device.create_shader_module(wgpu::include_wgsl!("shaders/filling_convergence.wgsl"));
+        let convergence_pipeline =
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("filling_convergence"),
+                module: &convergence_shader,
+                layout: None,
+                entry_point: "main",
+                compilation_options: Default::default(),
+                cache: None,
+            });
+        let convergence_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Filling Convergence Bindgroup"),
+            layout: &convergence_pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: convergence_tracker.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: dispatch_args.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: status.as_entire_binding(),
+                },
+            ],
+        });
+
+        FillingShader {
+            name,
+            textures,
+            convergence_tracker,
+            dispatch_args,
+            initial_dispatch: FillingShader::create_initial_dispatch(device, dims),
+            status,
+            readback,
+            indirect,
+            bind_group_layout,
+            bindgroups,
+            compute_pipeline,
+            convergence_bind_group,
+            convergence_pipeline,
+        }
+    }
+
+    fn create_initial_dispatch(device: &wgpu::Device, dims: (u32, u32)) -> wgpu::Buffer {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Filling Initial Dispatch"),
+            contents: bytemuck::cast_slice(&[dims.0.div_ceil(8), dims.1.div_ceil(8), 1]),
+            usage: wgpu::BufferUsages::COPY_SRC,
+        })
+    }
+
+    // Reallocate the ping-pong textures, e.g. when the output resolution changes
+    pub fn resize(&mut self, device: &wgpu::Device, dims: (u32, u32)) {
+        self.textures = FillingShader::create_textures(device, dims);
+        self.initial_dispatch = FillingShader::create_initial_dispatch(device, dims);
+        self.bindgroups = FillingShader::create_bind_groups(
+            device,
+            &self.bind_group_layout,
+            &self.textures,
+            &self.convergence_tracker,
+        );
+    }
+
+    fn create_textures(device: &wgpu::Device, dims: (u32, u32)) -> [(Texture, Texture); 2] {
         // TODO: can't bind depth textures to compute shaders. also change flags for compute
-        let textures = [
+        [
             (
                 Texture::new(
                     device,
                     dims,
-                    wgpu::TextureFormat::Bgra8Unorm,
+                    crate::renderer::WORKING_FORMAT,
                     wgpu::TextureUsages::COPY_SRC
                         | wgpu::TextureUsages::COPY_DST
-                        | wgpu::TextureUsages::STORAGE_BINDING,
+                        | wgpu::TextureUsages::STORAGE_BINDING
+                        | wgpu::TextureUsages::TEXTURE_BINDING,
                     "bs_tex_01",
                 ),
                 Texture::new(
@@ -32,7 +179,8 @@ impl FillingShader {
                     crate::renderer::DEPTH_STORAGE_FORMAT,
                     wgpu::TextureUsages::COPY_SRC
                         | wgpu::TextureUsages::COPY_DST
-                        | wgpu::TextureUsages::STORAGE_BINDING,
+                        | wgpu::TextureUsages::STORAGE_BINDING
+                        | wgpu::TextureUsages::TEXTURE_BINDING,
                     "bs_depth_tex_01",
                 ),
             ),
@@ -40,10 +188,11 @@ impl FillingShader {
                 Texture::new(
                     device,
                     dims,
-                    wgpu::TextureFormat::Bgra8Unorm,
+                    crate::renderer::WORKING_FORMAT,
                     wgpu::TextureUsages::COPY_SRC
                         | wgpu::TextureUsages::COPY_DST
-                        | wgpu::TextureUsages::STORAGE_BINDING,
+                        | wgpu::TextureUsages::STORAGE_BINDING
+                        | wgpu::TextureUsages::TEXTURE_BINDING,
                     "bs_tex_02",
                 ),
                 Texture::new(
@@ -52,51 +201,28 @@ impl FillingShader {
                     crate::renderer::DEPTH_STORAGE_FORMAT,
                     wgpu::TextureUsages::COPY_SRC
                         | wgpu::TextureUsages::COPY_DST
-                        | wgpu::TextureUsages::STORAGE_BINDING,
+                        | wgpu::TextureUsages::STORAGE_BINDING
+                        | wgpu::TextureUsages::TEXTURE_BINDING,
                     "bs_depth_tex_02",
                 ),
             ),
-        ];
-
-        let convergence_tracker = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: None,
-            contents: &[0u8],
-            usage: wgpu::BufferUsages::COPY_SRC
-                | wgpu::BufferUsages::COPY_DST
-                | wgpu::BufferUsages::MAP_READ
-                | wgpu::BufferUsages::MAP_WRITE
-                | wgpu::BufferUsages::UNIFORM,
-        });
-        let (bindgroups, compute_pipeline) = FillingShader::create_compute_pipeline(
-            &device,
-            &textures,
-            &convergence_tracker,
-            shader,
-        );
-        FillingShader {
-            textures,
-            convergence_tracker,
-            bindgroups,
-            compute_pipeline,
-        }
+        ]
     }
 
     pub fn create_compute_pipeline(
         device: &wgpu::Device,
-        textures: &[(Texture, Texture); 2],
-        convergence_tracker: &wgpu::Buffer,
         shader: wgpu::ShaderModuleDescriptor,
-    ) -> ([wgpu::BindGroup; 2], wgpu::ComputePipeline) {
+    ) -> (wgpu::BindGroupLayout, wgpu::ComputePipeline) {
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("CS Bindgroup Layout"),
             entries: &[
                 wgpu::BindGroupLayoutEntry {
                     binding: 0,
                     visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::StorageTexture {
-                        access: wgpu::StorageTextureAccess::ReadOnly,
-                        format: wgpu::TextureFormat::Bgra8Unorm,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
                         view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
                     },
                     count: None,
                 },
@@ -105,7 +231,7 @@ impl FillingShader {
                     visibility: wgpu::ShaderStages::COMPUTE,
                     ty: wgpu::BindingType::StorageTexture {
                         access: wgpu::StorageTextureAccess::WriteOnly,
-                        format: wgpu::TextureFormat::Bgra8Unorm,
+                        format: crate::renderer::WORKING_FORMAT,
                         view_dimension: wgpu::TextureViewDimension::D2,
                     },
                     count: None,
@@ -114,19 +240,19 @@ impl FillingShader {
                     binding: 2,
                     visibility: wgpu::ShaderStages::COMPUTE,
                     ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
                         has_dynamic_offset: false,
-                        min_binding_size: Some(1.try_into().unwrap()),
+                        min_binding_size: None,
                     },
                     count: None,
                 },
                 wgpu::BindGroupLayoutEntry {
                     binding: 3,
                     visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::StorageTexture {
-                        access: wgpu::StorageTextureAccess::ReadOnly,
-                        format: crate::renderer::DEPTH_STORAGE_FORMAT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
                         view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
                     },
                     count: None,
                 },
@@ -143,11 +269,34 @@ impl FillingShader {
             ],
         });
 
+        let compute_shader = device.create_shader_module(shader);
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("background_filling_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("background_filling"),
+            module: &compute_shader,
+            layout: Some(&layout),
+            entry_point: "main",
+            compilation_options: Default::default(),
+            cache: None,
+        });
+        (bind_group_layout, compute_pipeline)
+    }
+
+    fn create_bind_groups(
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        textures: &[(Texture, Texture); 2],
+        convergence_tracker: &wgpu::Buffer,
+    ) -> [wgpu::BindGroup; 2] {
         let create_bind_group =
             |color0: &Texture, depth0: &Texture, color1: &Texture, depth1: &Texture| {
                 device.create_bind_group(&wgpu::BindGroupDescriptor {
                     label: Some("CS Bindgroup"),
-                    layout: &bind_group_layout,
+                    layout: bind_group_layout,
                     entries: &[
                         wgpu::BindGroupEntry {
                             binding: 0,
@@ -174,8 +323,7 @@ impl FillingShader {
                     ],
                 })
             };
-        dbg!(textures[0].0.texture.format());
-        let bind_groups = [
+        [
             create_bind_group(
                 &textures[0].0,
                 &textures[0].1,
@@ -188,29 +336,17 @@ impl FillingShader {
                 &textures[0].0,
                 &textures[0].1,
             ),
-        ];
-
-        let compute_shader = device.create_shader_module(shader);
-        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("background_filling_layout"),
-            bind_group_layouts: &[&bind_group_layout],
-            push_constant_ranges: &[],
-        });
-        let compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: Some("background_filling"),
-            module: &compute_shader,
-            layout: Some(&layout),
-            entry_point: "main",
-        });
-        (bind_groups, compute_pipeline)
+        ]
     }
 
+    // Fill from the initial textures for up to max_iters iterations. See output for where the result ends up
     pub fn run(
         &self,
         command_encoder: &mut wgpu::CommandEncoder,
         initial_texture: &Texture,
         initial_depth: &Texture,
-        iters: u32,
+        max_iters: u32,
+        mut profiler: Option<&mut Profiler>,
     ) {
         let dims = (
             initial_texture.texture.width(),
@@ -254,22 +390,76 @@ impl FillingShader {
                 depth_or_array_layers: 1,
             },
         );
+        command_encoder.clear_buffer(&self.convergence_tracker, 0, None);
+        command_encoder.clear_buffer(&self.status, 0, None);
+        command_encoder.copy_buffer_to_buffer(
+            &self.initial_dispatch,
+            0,
+            &self.dispatch_args,
+            0,
+            STATUS_SIZE,
+        );
+        if let Some(profiler) = profiler.as_deref_mut() {
+            profiler.timestamp(command_encoder, &format!("{}_copy", self.name));
+        }
         {
             let mut compute_pass =
                 command_encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
                     label: Some("bf_compute_pass"),
                     ..Default::default()
                 });
-            compute_pass.set_pipeline(&self.compute_pipeline);
-            for iter in 0..iters {
+            // The cap still decides how many iterations are encoded, converged ones just dispatch nothing
+            for iter in 0..max_iters {
+                compute_pass.set_pipeline(&self.compute_pipeline);
                 compute_pass.set_bind_group(0, &self.bindgroups[(iter % 2) as usize], &[]);
-                compute_pass.dispatch_workgroups((dims.0 + 7) / 8, (dims.1 + 7) / 8, 1);
+                if self.indirect {
+                    compute_pass.dispatch_workgroups_indirect(&self.dispatch_args, 0);
+                } else {
+                    compute_pass.dispatch_workgroups(dims.0.div_ceil(8), dims.1.div_ceil(8), 1);
+                }
+                compute_pass.set_pipeline(&self.convergence_pipeline);
+                compute_pass.set_bind_group(0, &self.convergence_bind_group, &[]);
+                compute_pass.dispatch_workgroups(1, 1, 1);
             }
         }
+        command_encoder.copy_buffer_to_buffer(&self.status, 0, &self.readback, 0, STATUS_SIZE);
+        if let Some(profiler) = profiler {
+            profiler.timestamp(command_encoder, self.name);
+        }
     }
 
-    pub fn count(&self) -> u32 {
-        todo!()
+    // Iterations run, then changed and empty pixels of the last one. Only valid once the frame that ran
+    // this shader has been submitted, and waits for it to finish
+    fn read_status(&self, device: &wgpu::Device) -> [u32; 3] {
+        let slice = self.readback.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |r| r.unwrap());
+        device.poll(wgpu::Maintain::Wait);
+        let status: Vec<u32> = bytemuck::cast_slice(&slice.get_mapped_range()[..]).to_vec();
+        self.readback.unmap();
+        [status[0], status[1], status[2]]
+    }
+
+    // Ping-pong pair holding the result of iters iterations. Iteration i writes textures[(i + 1) % 2], and
+    // with no iterations the copied input is left in textures[0]
+    pub fn output(&self, iters: u32) -> (&Texture, &Texture) {
+        let (color, depth) = &self.textures[(iters % 2) as usize];
+        (color, depth)
     }
-}
 
+    // Pixels the final iteration of the last run changed, 0 once filling converged
+    pub fn count(&self, device: &wgpu::Device) -> u32 {
+        self.read_status(device)[1]
+    }
+
+    // How the last run went. count is the pixels its final iteration changed: filling stops once that
+    // reaches 0, so anything else means the iteration cap was hit first
+    pub fn stats(&self, device: &wgpu::Device) -> FillingStats {
+        let [iterations, count, empty] = self.read_status(device);
+        FillingStats {
+            stage: self.name,
+            iterations,
+            count,
+            empty,
+        }
+    }
+}
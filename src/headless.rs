@@ -1,11 +1,34 @@
-use crate::renderer::Renderer;
+use crate::background::Background;
+use crate::compute_raster::RasterMode;
+use crate::debug_view::DisplayMode;
+use crate::depth_of_field::DepthOfField;
+use crate::motion::{Motion, MotionPreset};
+use crate::poses;
+use crate::quilt::Quilt;
+use crate::renderer::{AspectMode, Renderer};
+use crate::scenes::Scene;
+use crate::stereo::{Stereo, StereoMode};
+use crate::transforms::{Frame, TransformsFile};
+use crate::view_params::{self, Screen};
+use crate::vr::{Vr, VrLayout};
 use base64::Engine as _;
+use clap::ValueEnum;
+use nalgebra::{Matrix4, Point3, Vector3};
 use std::io::prelude::*;
 
 pub struct HeadlessRenderer {
     renderer: Renderer,
     stdin: std::io::StdinLock<'static>,
     buf: String,
+    // Kept while stereo is off so interaxial/convergence survive toggling
+    stereo: Stereo,
+    // Same for VR settings while VR screenshots are off
+    vr: Vr,
+    // And the aperture while depth of field is off
+    depth_of_field: DepthOfField,
+    quilt: Quilt,
+    views_dir: String,
+    transforms: Option<TransformsFile>,
 }
 
 fn parse_num(num: &str) -> Result<f32, Box<dyn std::error::Error>> {
@@ -16,12 +39,33 @@ fn parse_num(num: &str) -> Result<f32, Box<dyn std::error::Error>> {
     }
 }
 
+// Layer indices are whole numbers, so "-1" or "1.7" is an error rather than a nearby layer
+fn parse_index(index: &str) -> Result<usize, Box<dyn std::error::Error>> {
+    index
+        .parse::<usize>()
+        .map_err(|e| format!("Invalid layer index {index}: {e}").into())
+}
+
 impl HeadlessRenderer {
-    pub fn new(renderer: Renderer) -> Self {
+    pub fn new(
+        renderer: Renderer,
+        stereo: Stereo,
+        vr: Vr,
+        depth_of_field: DepthOfField,
+        quilt: Quilt,
+        views_dir: String,
+        transforms: Option<TransformsFile>,
+    ) -> Self {
         HeadlessRenderer {
             renderer,
             stdin: std::io::stdin().lock(),
             buf: String::new(),
+            stereo,
+            vr,
+            depth_of_field,
+            quilt,
+            views_dir,
+            transforms,
         }
     }
 
@@ -29,8 +73,13 @@ impl HeadlessRenderer {
     pub fn run(&mut self) -> Result<(), std::io::Error> {
         loop {
             self.buf.clear();
-            self.stdin.read_line(&mut self.buf)?;
-            self.parse_line().unwrap();
+            if self.stdin.read_line(&mut self.buf)? == 0 {
+                return Ok(());
+            }
+            // A bad command shouldn't take the session down with it
+            if let Err(e) = self.parse_line() {
+                println!("Error: {e}");
+            }
         }
     }
 
@@ -54,11 +103,237 @@ impl HeadlessRenderer {
                     .view_params
                     .set_roll(self.renderer.view_params.roll() + num);
             }
+            Some(("eye", args)) => {
+                // eye <x> <y> <z>, absolute position (e.g. from a head tracker)
+                let coords = args
+                    .split_whitespace()
+                    .map(parse_num)
+                    .collect::<Result<Vec<f32>, _>>()?;
+                if let [x, y, z] = coords[..] {
+                    self.renderer.view_params.set_eye(Point3::new(x, y, z));
+                } else {
+                    println!("Invalid Command!");
+                }
+            }
+            Some(("screen", "off")) => self.renderer.view_params.set_screen(None),
+            Some(("screen", args)) => {
+                // screen <lower left xyz> <lower right xyz> <upper left xyz> [near far]
+                let coords = args
+                    .split_whitespace()
+                    .map(parse_num)
+                    .collect::<Result<Vec<f32>, _>>()?;
+                let (near, far) = match coords.len() {
+                    9 => (0.01, 100.0),
+                    11 => (coords[9], coords[10]),
+                    _ => {
+                        println!("Invalid Command!");
+                        return Ok(());
+                    }
+                };
+                self.renderer.view_params.set_screen(Some(Screen {
+                    lower_left: [coords[0], coords[1], coords[2]],
+                    lower_right: [coords[3], coords[4], coords[5]],
+                    upper_left: [coords[6], coords[7], coords[8]],
+                    near,
+                    far,
+                }));
+            }
+            Some(("load_scene", args)) => {
+                if let Some((image_path, depth_path)) = args.split_once(' ') {
+                    let (image, depth) = Scene {
+                        image_path: image_path.to_string(),
+                        depth_path: depth_path.to_string(),
+                    }
+                    .load()?;
+                    self.renderer.load_scene(&image, &depth)?;
+                } else {
+                    println!("Invalid Command!");
+                }
+            }
+            Some(("add_layer", args)) => {
+                if let Some((image_path, depth_path)) = args.split_once(' ') {
+                    let scene = Scene {
+                        image_path: image_path.to_string(),
+                        depth_path: depth_path.to_string(),
+                    };
+                    let (image, depth) = scene.load()?;
+                    println!(
+                        "{}",
+                        self.renderer.add_layer(&scene.name(), &image, &depth)?
+                    );
+                } else {
+                    println!("Invalid Command!");
+                }
+            }
+            Some(("remove_layer", index)) => {
+                self.renderer.remove_layer(parse_index(index)?)?;
+            }
+            Some(("layer", args)) => layer_command(&mut self.renderer, args)?,
+            Some(("resolution", args)) => {
+                if let Some((width, height)) = args.split_once(' ') {
+                    self.renderer
+                        .set_size((parse_num(width)? as u32, parse_num(height)? as u32))?;
+                } else {
+                    println!("Invalid Command!");
+                }
+            }
+            Some(("supersampling", num)) => {
+                self.renderer.set_supersampling(parse_num(num)? as u32);
+            }
+            Some(("background", spec)) => {
+                self.renderer.set_background(&Background::parse(spec)?);
+            }
+            Some(("raster", mode)) => {
+                self.renderer.raster_mode = RasterMode::from_str(mode, true)?;
+            }
+            Some(("mode", mode)) => {
+                self.renderer.display_mode = DisplayMode::from_str(mode, true)?;
+            }
+            Some(("aspect", mode)) => {
+                self.renderer
+                    .set_aspect_mode(AspectMode::from_str(mode, true)?);
+            }
+            Some(("stereo", "off")) => self.renderer.stereo = None,
+            Some(("stereo", mode)) => {
+                self.stereo.mode = StereoMode::from_str(mode, true)?;
+                self.renderer.stereo = Some(self.stereo);
+            }
+            Some(("interaxial", num)) => {
+                self.stereo.interaxial = parse_num(num)?;
+                self.vr.interaxial = self.stereo.interaxial;
+                self.renderer.stereo = self.renderer.stereo.map(|_| self.stereo);
+                self.renderer.vr = self.renderer.vr.map(|_| self.vr);
+            }
+            Some(("convergence", num)) => {
+                self.stereo.convergence = parse_num(num)?;
+                self.renderer.stereo = self.renderer.stereo.map(|_| self.stereo);
+            }
+            Some(("dof", "off")) => self.renderer.depth_of_field = None,
+            Some(("dof", args)) => {
+                // dof <aperture> [max radius], both in output pixels
+                let values = args
+                    .split_whitespace()
+                    .map(parse_num)
+                    .collect::<Result<Vec<f32>, _>>()?;
+                match values[..] {
+                    [aperture] => {
+                        self.depth_of_field.aperture = aperture;
+                    }
+                    [aperture, max_radius] => {
+                        self.depth_of_field = DepthOfField {
+                            aperture,
+                            max_radius,
+                        };
+                    }
+                    _ => {
+                        println!("Invalid Command!");
+                        return Ok(());
+                    }
+                }
+                self.renderer.depth_of_field = Some(self.depth_of_field);
+            }
+            Some(("focus", "auto")) => self.renderer.view_params.set_focus(None),
+            Some(("focus", num)) => {
+                self.renderer.view_params.set_focus(Some(parse_num(num)?));
+            }
+            Some(("focus_at", args)) => {
+                // focus_at <x> <y>, in output pixels of the current view
+                if let Some((x, y)) = args.split_once(' ') {
+                    self.renderer.update_camera();
+                    self.renderer.render(true, true)?;
+                    match self
+                        .renderer
+                        .focus_at((parse_num(x)? as u32, parse_num(y)? as u32))?
+                    {
+                        Some(focus) => println!("{focus}"),
+                        None => println!("Nothing at {x} {y}"),
+                    }
+                } else {
+                    println!("Invalid Command!");
+                }
+            }
+            Some(("vr", "off")) => self.renderer.vr = None,
+            Some(("vr", layout)) => {
+                self.vr.layout = VrLayout::from_str(layout, true)?;
+                self.renderer.vr = Some(self.vr);
+            }
+            Some(("vr_resolution", num)) => {
+                let resolution = parse_num(num)? as u32;
+                // Cube faces are rendered at resolution x resolution, so it has to be a valid target size
+                self.renderer.check_size((resolution, resolution))?;
+                self.vr.resolution = resolution;
+                self.renderer.vr = self.renderer.vr.map(|_| self.vr);
+            }
+            Some(("quilt", filename)) => {
+                self.renderer.render_quilt(&self.quilt)?.save(filename)?;
+            }
+            Some(("quilt_layout", args)) => {
+                // quilt_layout <views> <columns> <rows> <view cone degrees> <width> <height>
+                let values = args
+                    .split_whitespace()
+                    .map(parse_num)
+                    .collect::<Result<Vec<f32>, _>>()?;
+                if let [views, columns, rows, view_cone, width, height] = values[..] {
+                    self.quilt = Quilt::new(
+                        views as u32,
+                        columns as u32,
+                        rows as u32,
+                        view_cone,
+                        width as u32,
+                        height as u32,
+                    )?;
+                } else {
+                    println!("Invalid Command!");
+                }
+            }
+            Some(("transforms", path)) => {
+                // Start recording cameras for save_screenshot in a new transforms.json
+                self.transforms = Some(TransformsFile::new(path));
+            }
+            Some(("save_view", name)) => {
+                view_params::save_named_view(&self.renderer.view_params, &self.views_dir, name)?;
+            }
+            Some(("load_view", name)) => {
+                self.renderer.view_params = view_params::load_named_view(&self.views_dir, name)?;
+                self.renderer.update_camera();
+            }
+            Some(("motion", args)) => {
+                // motion <preset> <duration> <amplitude> <fps> <output_dir> [focus_to]
+                let args: Vec<&str> = args.split_whitespace().collect();
+                if let [preset, duration, amplitude, fps, output_dir, ref focus_to @ ..] = args[..]
+                {
+                    let motion = Motion::new(
+                        MotionPreset::from_str(preset, true)?,
+                        parse_num(duration)?,
+                        parse_num(amplitude)?,
+                        parse_num(fps)?,
+                        focus_to.first().copied().map(parse_num).transpose()?,
+                    )?;
+                    let frames = motion.frames(&self.renderer.view_params);
+                    self.renderer.render_frames(&frames, output_dir)?;
+                } else {
+                    println!("Invalid Command!");
+                }
+            }
 
+            Some(("render_poses", args)) => {
+                // render_poses <transforms.json or images.txt> <output_dir>
+                if let Some((path, output_dir)) = args.split_once(' ') {
+                    let frames = poses::load_poses(path, self.renderer.size())?;
+                    self.renderer
+                        .render_named_frames(&frames, output_dir.trim())?;
+                } else {
+                    println!("Invalid Command!");
+                }
+            }
+
+            Some(("save_mask", "on")) => self.renderer.save_mask = true,
+            Some(("save_mask", "off")) => self.renderer.save_mask = false,
             Some(("save_screenshot", filename)) => {
                 self.renderer.update_camera();
                 self.renderer.render(true, true)?;
-                self.renderer.save_screenshot(filename)?;
+                self.renderer
+                    .save_screenshot(filename, self.transforms.as_mut())?;
             }
             None => match self.buf.trim_end() {
                 "screenshot" => {
@@ -72,6 +347,47 @@ impl HeadlessRenderer {
                     let base64_data =
                         base64::engine::general_purpose::STANDARD.encode(&output_buffer);
                     println!("{base64_data}");
+                    // With camera recording enabled, follow the image with its transforms.json frame
+                    if self.transforms.is_some() {
+                        let frame = Frame::new(
+                            "",
+                            &self.renderer.fit_to_output(&self.renderer.view_params),
+                            self.renderer.size(),
+                        );
+                        println!("{}", serde_json::to_string(&frame)?);
+                    }
+                }
+                // Render the current view with GPU timestamps and print each stage's time as JSON
+                "stats" => {
+                    let profiling = self.renderer.profiling();
+                    self.renderer.set_profiling(true)?;
+                    self.renderer.update_camera();
+                    self.renderer.render(true, true)?;
+                    println!("{}", serde_json::to_string(&self.renderer.frame_stats())?);
+                    self.renderer.set_profiling(profiling)?;
+                }
+                // Render the current view and print how many iterations each filling shader ran, how many
+                // pixels its last iteration changed and how many it left empty as JSON
+                "filling" => {
+                    self.renderer.update_camera();
+                    self.renderer.render(true, true)?;
+                    println!(
+                        "{}",
+                        serde_json::to_string(&self.renderer.filling_stats(true, true))?
+                    );
+                }
+                "mask" => {
+                    self.renderer.update_camera();
+                    self.renderer.render(true, true)?;
+
+                    let mask = self.renderer.read_mask()?;
+                    let mut output_buffer = Vec::new();
+                    let encoder = image::codecs::png::PngEncoder::new(&mut output_buffer);
+                    mask.write_with_encoder(encoder)?;
+                    println!(
+                        "{}",
+                        base64::engine::general_purpose::STANDARD.encode(&output_buffer)
+                    );
                 }
                 _ => println!("Invalid Command!"),
             },
@@ -80,3 +396,48 @@ impl HeadlessRenderer {
         Ok(())
     }
 }
+
+// layer <index> translate <x y z> | rotate <axis x y z> <degrees> | scale <factor> | show | hide | reset
+// | matrix <16 numbers, column major>
+fn layer_command(renderer: &mut Renderer, args: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut words = args.split_whitespace();
+    let (Some(index), Some(operation)) = (words.next(), words.next()) else {
+        println!("Invalid Command!");
+        return Ok(());
+    };
+    let numbers = words.map(parse_num).collect::<Result<Vec<f32>, _>>()?;
+    let Some(layer) = renderer.layers.get_mut(parse_index(index)?) else {
+        println!("No layer {index}");
+        return Ok(());
+    };
+    match (operation, numbers.as_slice()) {
+        ("translate", &[x, y, z]) => layer.translate(Vector3::new(x, y, z)),
+        ("rotate", &[x, y, z, degrees]) => layer.rotate(Vector3::new(x, y, z), degrees),
+        ("scale", &[factor]) => layer.scale(factor),
+        ("show", &[]) => layer.visible = true,
+        ("hide", &[]) => layer.visible = false,
+        ("reset", &[]) => layer.model = Matrix4::identity(),
+        ("matrix", values) if values.len() == 16 => {
+            layer.model = Matrix4::from_column_slice(values)
+        }
+        _ => {
+            println!("Invalid Command!");
+            return Ok(());
+        }
+    }
+    renderer.update_camera();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layer_indices_are_whole_numbers() {
+        assert_eq!(parse_index("2").unwrap(), 2);
+        for index in ["-1", "1.7", "one", ""] {
+            assert!(parse_index(index).is_err(), "{index}");
+        }
+    }
+}
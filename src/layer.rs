@@ -0,0 +1,99 @@
+use nalgebra::{Matrix4, Rotation3, Unit, Vector3};
+use wgpu::util::DeviceExt;
+
+use crate::renderer::Vertex;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct LayerUniform {
+    // Projection * view * model
+    view: [f32; 16],
+    // Where this layer's points start when the compute rasterizer numbers every layer's points in order
+    first_point: u32,
+    _padding: [u32; 3],
+}
+
+// One point cloud in the scene, drawn with its own model matrix into the same depth-tested target as the
+// rest. Layer 0 is the scene the renderer was created with, the one load_scene replaces
+pub struct Layer {
+    pub name: String,
+    pub vertex_buffer: wgpu::Buffer,
+    // Placement in world space, applied before the camera
+    pub model: Matrix4<f32>,
+    pub visible: bool,
+    pub uniform_buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl Layer {
+    pub fn new(
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        name: &str,
+        vertex_buffer: wgpu::Buffer,
+    ) -> Self {
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Layer"),
+            contents: bytemuck::cast_slice(&[LayerUniform {
+                view: Matrix4::identity().as_slice().try_into().unwrap(),
+                first_point: 0,
+                _padding: [0; 3],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Camera Bindgroup"),
+            layout: camera_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+        });
+        Layer {
+            name: name.to_string(),
+            vertex_buffer,
+            model: Matrix4::identity(),
+            visible: true,
+            uniform_buffer,
+            bind_group,
+        }
+    }
+
+    pub fn point_count(&self) -> u32 {
+        (self.vertex_buffer.size() as usize / std::mem::size_of::<Vertex>()) as u32
+    }
+
+    pub fn write(&self, queue: &wgpu::Queue, view_projection: &Matrix4<f32>, first_point: u32) {
+        let uniform = LayerUniform {
+            view: (view_projection * self.model)
+                .as_slice()
+                .try_into()
+                .unwrap(),
+            first_point,
+            _padding: [0; 3],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    pub fn translate(&mut self, offset: Vector3<f32>) {
+        self.model = Matrix4::new_translation(&offset) * self.model;
+    }
+
+    // Rotations and scaling pivot on the layer's own origin, wherever it has been moved to
+    pub fn rotate(&mut self, axis: Vector3<f32>, degrees: f32) {
+        let rotation = Rotation3::from_axis_angle(&Unit::new_normalize(axis), degrees.to_radians());
+        self.about_origin(rotation.to_homogeneous());
+    }
+
+    pub fn scale(&mut self, factor: f32) {
+        self.about_origin(Matrix4::new_scaling(factor));
+    }
+
+    fn about_origin(&mut self, transform: Matrix4<f32>) {
+        let origin: Vector3<f32> = self.model.fixed_view::<3, 1>(0, 3).into();
+        self.model = Matrix4::new_translation(&origin)
+            * transform
+            * Matrix4::new_translation(&-origin)
+            * self.model;
+    }
+}
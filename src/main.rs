@@ -1,37 +1,178 @@
+use adapter::{AdapterOptions, AdapterSelector, Backend};
+use background::Background;
 use headless::HeadlessRenderer;
-use image::{io::Reader as ImageReader, ImageBuffer, Luma, Rgba};
+use image::{io::Reader as ImageReader, ImageBuffer};
 
 use clap::Parser;
-use nalgebra::Vector3;
-use winit::event::{Event, WindowEvent};
+use compute_raster::RasterMode;
+use debug_view::DisplayMode;
+use depth_of_field::DepthOfField;
+use equirect::InputProjection;
+use motion::{Motion, MotionPreset};
+use nalgebra::{Matrix4, Vector3};
+use quilt::Quilt;
+use renderer::AspectMode;
+use scenes::{ImagePair, Scene};
+use stereo::{Stereo, StereoMode};
+use transforms::TransformsFile;
+use vr::{Vr, VrLayout};
+use winit::event::{ElementState, Event, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent};
 
+mod adapter;
+mod background;
+mod compute_raster;
+mod debug_view;
+mod depth_of_field;
+mod downsample;
+mod equirect;
 mod filling_shader;
 mod headless;
+mod layer;
+mod motion;
+mod poses;
+mod profiler;
+mod provenance;
+mod quilt;
 mod renderer;
+mod scenes;
+mod stereo;
 mod texture;
+mod transforms;
 mod view_params;
+mod vr;
 
 #[derive(Parser)]
 struct Args {
     #[arg(long)]
     headless: bool,
-    image_path: String,
-    depth_path: String,
+    /// Render a motion preset to a frame sequence instead of opening the viewer
+    #[arg(long, value_enum)]
+    motion: Option<MotionPreset>,
+    /// Length of the motion in seconds
+    #[arg(long, default_value_t = 4.0)]
+    duration: f32,
+    /// Strength of the motion: the radius in scene units for orbit and wiggle, and a fraction of the
+    /// distance to the look-at point (below 1) for ken-burns and dolly-zoom
+    #[arg(long, default_value_t = 0.1)]
+    amplitude: f32,
+    /// Frames per second of the rendered sequence
+    #[arg(long, default_value_t = 30.0)]
+    fps: f32,
+    /// Pull focus to this depth over the course of the motion (needs --aperture)
+    #[arg(long)]
+    focus_to: Option<f32>,
+    /// Render every camera in a NeRF transforms.json or COLMAP images.txt (with cameras.txt alongside)
+    #[arg(long)]
+    poses: Option<String>,
+    /// Directory frame sequences are written to
+    #[arg(long, default_value = "frames")]
+    output_dir: String,
+    /// Directory named views and bookmarks are saved to
+    #[arg(long, default_value = "views")]
+    views_dir: String,
+    /// Name of a saved view to start from
+    #[arg(long)]
+    view: Option<String>,
+    /// Render a stereo pair composed into a single frame
+    #[arg(long, value_enum)]
+    stereo: Option<StereoMode>,
+    /// Distance between the stereo cameras in scene units (the image is 2 units wide)
+    #[arg(long, default_value_t = 0.05)]
+    interaxial: f32,
+    /// Distance at which the eyes converge, defaults to the distance to the look_at point
+    #[arg(long)]
+    convergence: Option<f32>,
+    /// Render a light-field quilt to this file instead of opening the viewer
+    #[arg(long)]
+    quilt: Option<String>,
+    #[arg(long, default_value_t = 48)]
+    quilt_views: u32,
+    #[arg(long, default_value_t = 8)]
+    quilt_columns: u32,
+    #[arg(long, default_value_t = 6)]
+    quilt_rows: u32,
+    /// Horizontal angle covered by the quilt's camera sweep, in degrees
+    #[arg(long, default_value_t = 40.0)]
+    quilt_cone: f32,
+    #[arg(long, default_value_t = 4096)]
+    quilt_width: u32,
+    #[arg(long, default_value_t = 4096)]
+    quilt_height: u32,
+    /// Render at WIDTHxHEIGHT instead of the input's size (e.g. 3840x2160)
+    #[arg(long, value_parser = parse_size)]
+    output_size: Option<(u32, u32)>,
+    /// How the input is placed when the output has a different aspect ratio
+    #[arg(long, value_enum, default_value = "fit")]
+    aspect: AspectMode,
+    /// Render at this many times the output resolution and downsample, for antialiasing
+    #[arg(long, default_value_t = 1)]
+    supersampling: u32,
+    /// What's left in unfilled holes: a color like #1e1e1e, "transparent", or the path of a backdrop image
+    #[arg(long, value_parser = parse_background, default_value = "#000000")]
+    background: Background,
+    /// Turn on depth of field, blurring points infinitely far behind the focus plane by this many pixels
+    #[arg(long)]
+    aperture: Option<f32>,
+    /// Largest blur radius in pixels, reached by points close in front of the camera
+    #[arg(long, default_value_t = 16.0)]
+    max_blur: f32,
+    /// Depth that's in focus, along the view axis. Defaults to the distance to the look_at point
+    #[arg(long)]
+    focus: Option<f32>,
+    /// Save screenshots as stereo equirect images for VR players (uses --interaxial)
+    #[arg(long, value_enum)]
+    vr: Option<VrLayout>,
+    /// Height of each eye's equirect image
+    #[arg(long, default_value_t = 2048)]
+    vr_resolution: u32,
+    /// How the input image is unprojected. Equirect treats it as a 360° panorama with the camera inside
+    #[arg(long, value_enum, default_value = "planar")]
+    input_projection: InputProjection,
+    /// How points are rasterized: a hardware point-list pass, or compute shaders resolving visibility with atomics
+    #[arg(long, value_enum, default_value = "hardware")]
+    raster: RasterMode,
+    /// What to show: the image, a depth colormap, the hole mask, normals, or one stage of the pipeline
+    #[arg(long, value_enum, default_value = "color")]
+    display_mode: DisplayMode,
+    /// Save a <name>-mask.png provenance mask next to every screenshot
+    #[arg(long)]
+    save_mask: bool,
+    /// Graphics API to run on
+    #[arg(long, value_enum, default_value = "vulkan")]
+    backend: Backend,
+    /// Adapter to use, by index or by part of its name (e.g. "intel"). Defaults to the high performance one
+    #[arg(long, value_parser = parse_adapter)]
+    adapter: Option<AdapterSelector>,
+    /// Only use a software adapter such as llvmpipe or lavapipe
+    #[arg(long)]
+    fallback_adapter: bool,
+    /// List the adapters the backend can see, with their limits and any features the renderer would do without
+    #[arg(long)]
+    adapter_info: bool,
+    /// File listing image and depth map pairs, one pair per line. n/b step through them in the viewer, and
+    /// quilts, motion presets and poses are rendered for each one
+    #[arg(long)]
+    scenes: Option<String>,
+    /// Add another image and depth map as a layer that can be moved on its own (repeatable)
+    #[arg(long, num_args = 2, value_names = ["IMAGE", "DEPTH"])]
+    layer: Vec<String>,
+    /// Record the camera of every screenshot in this NeRF style transforms.json
+    #[arg(long)]
+    transforms: Option<String>,
+    #[arg(required_unless_present_any = ["adapter_info", "scenes"])]
+    image_path: Option<String>,
+    #[arg(required_unless_present_any = ["adapter_info", "scenes"])]
+    depth_path: Option<String>,
     before_path: Option<String>,
     mask_path: Option<String>,
 }
 
-fn get_image(
-    args: &Args,
-) -> Result<
-    (
-        ImageBuffer<Rgba<u8>, Vec<u8>>,
-        ImageBuffer<Luma<u8>, Vec<u8>>,
-    ),
-    Box<dyn std::error::Error>,
-> {
-    let img = ImageReader::open(&args.image_path)?.decode()?.to_rgba8();
-    let mut depth = ImageReader::open(&args.depth_path)?.decode()?.to_luma8();
+fn get_image(args: &Args) -> Result<ImagePair, Box<dyn std::error::Error>> {
+    let (Some(image_path), Some(depth_path)) = (&args.image_path, &args.depth_path) else {
+        return Err("An image and a depth map are required".into());
+    };
+    let img = ImageReader::open(image_path)?.decode()?.to_rgba8();
+    let mut depth = ImageReader::open(depth_path)?.decode()?.to_luma8();
     //depth.save("/tmp/foo.png")?;
     assert_eq!(img.dimensions(), depth.dimensions());
 
@@ -40,8 +181,8 @@ fn get_image(
 
     if let Some(before_path) = &args.before_path {
         if let Some(mask_path) = &args.mask_path {
-            let before = ImageReader::open(&before_path)?.decode()?.to_rgba8();
-            let mask = ImageReader::open(&mask_path)?.decode()?.to_luma8();
+            let before = ImageReader::open(before_path)?.decode()?.to_rgba8();
+            let mask = ImageReader::open(mask_path)?.decode()?.to_luma8();
             for (i, (((maskrow, beforerow), afterrow), depthrow)) in mask
                 .rows()
                 .zip(before.rows())
@@ -76,14 +217,80 @@ fn get_image(
     //test_image.save("/tmp/foo2.png")?;
     Ok((img, depth))
 }
+
+fn parse_size(size: &str) -> Result<(u32, u32), String> {
+    let (width, height) = size
+        .split_once('x')
+        .ok_or_else(|| format!("Expected WIDTHxHEIGHT, got {size}"))?;
+    let parse = |value: &str| {
+        value
+            .parse::<u32>()
+            .ok()
+            .filter(|value| *value > 0)
+            .ok_or_else(|| format!("Invalid size {size}"))
+    };
+    Ok((parse(width)?, parse(height)?))
+}
+
+fn parse_adapter(spec: &str) -> Result<AdapterSelector, String> {
+    Ok(AdapterSelector::parse(spec))
+}
+
+fn parse_background(spec: &str) -> Result<Background, String> {
+    Background::parse(spec).map_err(|e| e.to_string())
+}
+
+fn bookmark_slot(key: VirtualKeyCode) -> Option<usize> {
+    match key {
+        VirtualKeyCode::Key0 => Some(0),
+        VirtualKeyCode::Key1 => Some(1),
+        VirtualKeyCode::Key2 => Some(2),
+        VirtualKeyCode::Key3 => Some(3),
+        VirtualKeyCode::Key4 => Some(4),
+        VirtualKeyCode::Key5 => Some(5),
+        VirtualKeyCode::Key6 => Some(6),
+        VirtualKeyCode::Key7 => Some(7),
+        VirtualKeyCode::Key8 => Some(8),
+        VirtualKeyCode::Key9 => Some(9),
+        _ => None,
+    }
+}
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
-    let (image, depth) = get_image(&args).unwrap();
-    let dims = image.dimensions();
+    let adapter_options = AdapterOptions {
+        backend: args.backend,
+        adapter: args.adapter.clone(),
+        force_fallback: args.fallback_adapter,
+    };
+    if args.adapter_info {
+        adapter::print_adapter_info(&adapter_options);
+        return Ok(());
+    }
+    // The image on the command line comes first, followed by the scene list
+    let mut scenes = Vec::new();
+    if let (Some(image_path), Some(depth_path)) = (&args.image_path, &args.depth_path) {
+        scenes.push(Scene {
+            image_path: image_path.clone(),
+            depth_path: depth_path.clone(),
+        });
+    }
+    if let Some(path) = &args.scenes {
+        scenes.extend(scenes::load_scene_list(path)?);
+    }
+    let (image, depth) = match scenes.first() {
+        Some(_) if args.image_path.is_some() => get_image(&args).unwrap(),
+        Some(scene) => scene.load()?,
+        None => return Err("The scene list is empty".into()),
+    };
+    let dims = args.output_size.unwrap_or(image.dimensions());
 
     let events_loop = winit::event_loop::EventLoopBuilder::new().build();
 
-    let window = if !args.headless {
+    let window = if !args.headless
+        && args.motion.is_none()
+        && args.poses.is_none()
+        && args.quilt.is_none()
+    {
         let window = winit::window::WindowBuilder::new()
             .with_inner_size(winit::dpi::PhysicalSize::new(dims.0, dims.1))
             .build(&events_loop)?;
@@ -97,18 +304,144 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         window,
         image.clone(),
         depth.clone(),
+        args.input_projection,
+        &adapter_options,
         true,
         true,
-    ));
+    ))?;
+    renderer.set_aspect_mode(args.aspect);
+    renderer.raster_mode = args.raster;
+    renderer.display_mode = args.display_mode;
+    renderer.save_mask = args.save_mask;
+    if let Some(output_size) = args.output_size {
+        renderer.set_size(output_size)?;
+    }
+    renderer.set_supersampling(args.supersampling);
+    renderer.set_background(&args.background);
+    for paths in args.layer.chunks(2) {
+        let scene = Scene {
+            image_path: paths[0].clone(),
+            depth_path: paths[1].clone(),
+        };
+        let (image, depth) = scene.load()?;
+        renderer.add_layer(&scene.name(), &image, &depth)?;
+    }
+
+    if let Some(view) = &args.view {
+        renderer.view_params = view_params::load_named_view(&args.views_dir, view)?;
+        renderer.update_camera();
+    }
+
+    let stereo_settings = Stereo {
+        mode: args.stereo.unwrap_or(StereoMode::SideBySide),
+        interaxial: args.interaxial,
+        convergence: args.convergence.unwrap_or_else(|| {
+            (renderer.view_params.look_at() - renderer.view_params.eye()).magnitude()
+        }),
+    };
+    renderer.stereo = args.stereo.map(|_| stereo_settings);
+
+    // Cube faces are rendered at resolution x resolution, so with VR output on it has to be a valid target
+    // size. Without it the setting is unused and isn't checked
+    if args.vr.is_some() {
+        renderer.check_size((args.vr_resolution, args.vr_resolution))?;
+    }
+    let vr_settings = Vr {
+        layout: args.vr.unwrap_or(VrLayout::Vr180),
+        interaxial: args.interaxial,
+        resolution: args.vr_resolution,
+    };
+    renderer.vr = args.vr.map(|_| vr_settings);
+
+    let depth_of_field_settings = DepthOfField {
+        aperture: args.aperture.unwrap_or(8.0),
+        max_radius: args.max_blur,
+    };
+    renderer.depth_of_field = args.aperture.map(|_| depth_of_field_settings);
+    if args.focus.is_some() {
+        renderer.view_params.set_focus(args.focus);
+    }
 
-    if args.headless {
-        let mut headless_renderer = HeadlessRenderer::new(renderer);
+    let quilt = Quilt::new(
+        args.quilt_views,
+        args.quilt_columns,
+        args.quilt_rows,
+        args.quilt_cone,
+        args.quilt_width,
+        args.quilt_height,
+    )?;
+
+    let motion = args
+        .motion
+        .map(|preset| {
+            Motion::new(
+                preset,
+                args.duration,
+                args.amplitude,
+                args.fps,
+                args.focus_to,
+            )
+        })
+        .transpose()?;
+
+    if args.quilt.is_some() || args.motion.is_some() || args.poses.is_some() {
+        for (index, scene) in scenes.iter().enumerate() {
+            // The first scene is already loaded
+            if index > 0 {
+                let (image, depth) = scene.load()?;
+                renderer.load_scene(&image, &depth)?;
+            }
+            // With a scene list, every scene's output is named or put in a directory after its image
+            let scene_name = args.scenes.as_ref().map(|_| scene.name());
+            let output_dir = match &scene_name {
+                Some(name) => format!("{}/{name}", args.output_dir),
+                None => args.output_dir.clone(),
+            };
+            if let Some(path) = &args.quilt {
+                let path = std::path::Path::new(path);
+                let path = match &scene_name {
+                    Some(name) => path.with_file_name(format!(
+                        "{name}-{}",
+                        path.file_name().unwrap_or_default().to_string_lossy()
+                    )),
+                    None => path.to_path_buf(),
+                };
+                renderer.render_quilt(&quilt)?.save(path)?;
+            } else if let Some(motion) = &motion {
+                let frames = motion.frames(&renderer.view_params);
+                renderer.render_frames(&frames, &output_dir)?;
+            } else if let Some(poses) = &args.poses {
+                let frames = poses::load_poses(poses, renderer.size())?;
+                renderer.render_named_frames(&frames, &output_dir)?;
+            }
+        }
+    } else if args.headless {
+        let mut headless_renderer = HeadlessRenderer::new(
+            renderer,
+            stereo_settings,
+            vr_settings,
+            depth_of_field_settings,
+            quilt,
+            args.views_dir,
+            args.transforms.as_deref().map(TransformsFile::new),
+        );
         headless_renderer.run()?;
     } else {
         let mut changed = true;
+        let mut scene_index = 0;
+        // Layer moved by the shifted movement keys and shown or hidden by h
+        let mut selected_layer = 0;
         let mut img_count = 0;
         let mut background_shading_enabled = true;
         let mut occlusion_shading_enabled = false;
+        // Set when a filling cap changes, to say whether the new cap is still being hit
+        let mut report_caps = false;
+        let mut modifiers = winit::event::ModifiersState::empty();
+        // Last cursor position in the window, for click to focus
+        let mut cursor = (0.0, 0.0);
+        // Bookmarks are also saved to the views directory (as 0.json - 9.json) so they outlive the session
+        let mut bookmarks: [Option<view_params::ViewParams>; 10] = [None; 10];
+        let mut transforms = args.transforms.as_deref().map(TransformsFile::new);
 
         events_loop.run(move |e, _, ctrl| match e {
             Event::WindowEvent {
@@ -119,6 +452,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     .view_params
                     .set_pitch(renderer.view_params.pitch() + 0.01);
                 renderer.update_camera();
+                changed = true;
             }
             Event::WindowEvent {
                 event: WindowEvent::ReceivedCharacter('d'),
@@ -128,6 +462,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     .view_params
                     .set_pitch(renderer.view_params.pitch() - 0.01);
                 renderer.update_camera();
+                changed = true;
             }
             Event::WindowEvent {
                 event: WindowEvent::ReceivedCharacter('q'),
@@ -137,6 +472,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     .view_params
                     .set_yaw(renderer.view_params.yaw() + 0.01);
                 renderer.update_camera();
+                changed = true;
             }
             Event::WindowEvent {
                 event: WindowEvent::ReceivedCharacter('e'),
@@ -146,6 +482,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     .view_params
                     .set_yaw(renderer.view_params.yaw() - 0.01);
                 renderer.update_camera();
+                changed = true;
             }
             Event::WindowEvent {
                 event: WindowEvent::ReceivedCharacter('w'),
@@ -155,6 +492,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     .view_params
                     .set_roll(renderer.view_params.roll() + 0.01);
                 renderer.update_camera();
+                changed = true;
             }
             Event::WindowEvent {
                 event: WindowEvent::ReceivedCharacter('s'),
@@ -164,6 +502,72 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     .view_params
                     .set_roll(renderer.view_params.roll() - 0.01);
                 renderer.update_camera();
+                changed = true;
+            }
+            Event::WindowEvent {
+                event: WindowEvent::ReceivedCharacter(c @ ('i' | 'j' | 'k' | 'l' | 'u' | 'o')),
+                ..
+            } => {
+                // Walk around: i/k forward and back, j/l left and right, u/o down and up
+                let step = 0.02;
+                let (right, up, forward) = match c {
+                    'i' => (0.0, 0.0, step),
+                    'k' => (0.0, 0.0, -step),
+                    'j' => (-step, 0.0, 0.0),
+                    'l' => (step, 0.0, 0.0),
+                    'u' => (0.0, -step, 0.0),
+                    _ => (0.0, step, 0.0),
+                };
+                renderer.view_params.translate(right, up, forward);
+                renderer.update_camera();
+                changed = true;
+            }
+            Event::WindowEvent {
+                event:
+                    WindowEvent::ReceivedCharacter(c @ ('I' | 'J' | 'K' | 'L' | 'U' | 'O' | 'Q' | 'E')),
+                ..
+            } => {
+                // Move the selected layer along the world axes, or turn it about its vertical axis
+                let step = 0.02;
+                let layer = &mut renderer.layers[selected_layer];
+                match c {
+                    'I' => layer.translate(Vector3::new(0.0, 0.0, -step)),
+                    'K' => layer.translate(Vector3::new(0.0, 0.0, step)),
+                    'J' => layer.translate(Vector3::new(-step, 0.0, 0.0)),
+                    'L' => layer.translate(Vector3::new(step, 0.0, 0.0)),
+                    'U' => layer.translate(Vector3::new(0.0, -step, 0.0)),
+                    'O' => layer.translate(Vector3::new(0.0, step, 0.0)),
+                    'Q' => layer.rotate(Vector3::y(), 1.0),
+                    _ => layer.rotate(Vector3::y(), -1.0),
+                }
+                renderer.update_camera();
+                changed = true;
+            }
+            Event::WindowEvent {
+                event: WindowEvent::ReceivedCharacter('g'),
+                ..
+            } => {
+                selected_layer = (selected_layer + 1) % renderer.layers.len();
+                println!(
+                    "Layer {selected_layer}: {}",
+                    renderer.layers[selected_layer].name
+                );
+            }
+            Event::WindowEvent {
+                event: WindowEvent::ReceivedCharacter('h'),
+                ..
+            } => {
+                let layer = &mut renderer.layers[selected_layer];
+                layer.visible = !layer.visible;
+                changed = true;
+            }
+            Event::WindowEvent {
+                event: WindowEvent::ReceivedCharacter('G'),
+                ..
+            } => {
+                renderer.layers[selected_layer].model = Matrix4::identity();
+                renderer.update_camera();
+                changed = true;
             }
             Event::WindowEvent {
                 event: WindowEvent::ReceivedCharacter('f'),
@@ -171,19 +575,144 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             } => {
                 let now = std::time::Instant::now();
                 renderer
-                    .save_screenshot(&format!("screenshot-{img_count}.png"))
+                    .save_screenshot(&format!("screenshot-{img_count}.png"), transforms.as_mut())
                     .unwrap();
                 println!(
                     "Screenshot saved to screenshot-{img_count}.png in {:?}",
                     std::time::Instant::now() - now
                 );
                 img_count += 1;
+                // VR screenshots render other views through the window
+                changed = true;
+            }
+            Event::WindowEvent {
+                event: WindowEvent::ReceivedCharacter('v'),
+                ..
+            } => {
+                // Cycle through mono and the stereo modes
+                let mode = match renderer.stereo.map(|stereo| stereo.mode) {
+                    None => Some(StereoMode::SideBySide),
+                    Some(StereoMode::SideBySide) => Some(StereoMode::TopBottom),
+                    Some(StereoMode::TopBottom) => Some(StereoMode::Anaglyph),
+                    Some(StereoMode::Anaglyph) => None,
+                };
+                renderer.stereo = mode.map(|mode| Stereo {
+                    mode,
+                    ..stereo_settings
+                });
+                changed = true;
+            }
+            Event::WindowEvent {
+                event: WindowEvent::ReceivedCharacter('-'),
+                ..
+            } => {
+                renderer.set_supersampling(renderer.supersampling().saturating_sub(1));
+                println!("Supersampling: {}x", renderer.supersampling());
+                changed = true;
+            }
+            Event::WindowEvent {
+                event: WindowEvent::ReceivedCharacter('='),
+                ..
+            } => {
+                renderer.set_supersampling(renderer.supersampling() + 1);
+                println!("Supersampling: {}x", renderer.supersampling());
+                changed = true;
+            }
+            Event::WindowEvent {
+                event: WindowEvent::ReceivedCharacter('r'),
+                ..
+            } => {
+                renderer.raster_mode = match renderer.raster_mode {
+                    RasterMode::Hardware => RasterMode::Compute,
+                    RasterMode::Compute => RasterMode::Hardware,
+                };
+                println!("Rasterizer: {:?}", renderer.raster_mode);
+                changed = true;
+            }
+            Event::WindowEvent {
+                event: WindowEvent::ReceivedCharacter('m'),
+                ..
+            } => {
+                renderer.display_mode = renderer.display_mode.next();
+                println!("Display mode: {:?}", renderer.display_mode);
+                changed = true;
+            }
+            Event::WindowEvent {
+                event: WindowEvent::ReceivedCharacter(c @ ('n' | 'b')),
+                ..
+            } => {
+                // Step forwards or back through the scenes, wrapping around
+                let next = match c {
+                    'n' => (scene_index + 1) % scenes.len(),
+                    _ => (scene_index + scenes.len() - 1) % scenes.len(),
+                };
+                match scenes[next]
+                    .load()
+                    .and_then(|(image, depth)| renderer.load_scene(&image, &depth))
+                {
+                    Ok(()) => {
+                        scene_index = next;
+                        println!("Scene {next}: {}", scenes[next].image_path);
+                    }
+                    Err(e) => println!("Couldn't load {}: {e}", scenes[next].image_path),
+                }
+                changed = true;
+            }
+            Event::WindowEvent {
+                event: WindowEvent::ReceivedCharacter('p'),
+                ..
+            } => {
+                // Stage timings get printed after every frame while this is on
+                if let Err(e) = renderer.set_profiling(!renderer.profiling()) {
+                    println!("Can't profile: {e}");
+                }
+                changed = true;
+            }
+            Event::WindowEvent {
+                event: WindowEvent::ReceivedCharacter('z'),
+                ..
+            } => {
+                renderer.depth_of_field = match renderer.depth_of_field {
+                    Some(_) => None,
+                    None => Some(depth_of_field_settings),
+                };
+                println!("Depth of field: {:?}", renderer.depth_of_field);
+                changed = true;
+            }
+            Event::WindowEvent {
+                event: WindowEvent::ReceivedCharacter('Z'),
+                ..
+            } => {
+                // Back to focusing on the look_at point
+                renderer.view_params.set_focus(None);
+                changed = true;
+            }
+            Event::WindowEvent {
+                event: WindowEvent::CursorMoved { position, .. },
+                ..
+            } => cursor = (position.x, position.y),
+            Event::WindowEvent {
+                event:
+                    WindowEvent::MouseInput {
+                        state: ElementState::Pressed,
+                        button: MouseButton::Left,
+                        ..
+                    },
+                ..
+            } => {
+                match renderer.focus_at((cursor.0 as u32, cursor.1 as u32)) {
+                    Ok(Some(focus)) => println!("Focus: {focus:.3}"),
+                    Ok(None) => println!("Nothing to focus on there"),
+                    Err(e) => println!("Can't focus: {e}"),
+                }
+                changed = true;
             }
             Event::WindowEvent {
                 event: WindowEvent::ReceivedCharacter('t'),
                 ..
             } => {
                 background_shading_enabled = !background_shading_enabled;
+                changed = true;
             }
             Event::WindowEvent {
                 event: WindowEvent::ReceivedCharacter('y'),
@@ -191,6 +720,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             } => {
                 // enable background filling
                 occlusion_shading_enabled = !occlusion_shading_enabled;
+                changed = true;
             }
             Event::WindowEvent {
                 event: WindowEvent::ReceivedCharacter('['),
@@ -199,6 +729,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 // enable background filling
                 renderer.background_shading_iters =
                     std::cmp::max(1, renderer.background_shading_iters.saturating_sub(1));
+                report_caps = true;
+                changed = true;
             }
             Event::WindowEvent {
                 event: WindowEvent::ReceivedCharacter(']'),
@@ -207,6 +739,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 // enable background filling
                 renderer.background_shading_iters =
                     renderer.background_shading_iters.saturating_add(1);
+                report_caps = true;
+                changed = true;
             }
             Event::WindowEvent {
                 event: WindowEvent::ReceivedCharacter(';'),
@@ -215,6 +749,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 // enable background filling
                 renderer.occlusion_shading_iters =
                     std::cmp::max(1, renderer.occlusion_shading_iters.saturating_sub(1));
+                report_caps = true;
+                changed = true;
             }
             Event::WindowEvent {
                 event: WindowEvent::ReceivedCharacter('\''),
@@ -223,6 +759,64 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 // enable background filling
                 renderer.occlusion_shading_iters =
                     renderer.occlusion_shading_iters.saturating_add(1);
+                report_caps = true;
+                changed = true;
+            }
+            Event::WindowEvent {
+                event: WindowEvent::ModifiersChanged(state),
+                ..
+            } => modifiers = state,
+            Event::WindowEvent {
+                event:
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(key),
+                                ..
+                            },
+                        ..
+                    },
+                ..
+            } => {
+                // Ctrl + number stores a bookmark, number on its own recalls it
+                if let Some(slot) = bookmark_slot(key) {
+                    let name = slot.to_string();
+                    if modifiers.ctrl() {
+                        bookmarks[slot] = Some(renderer.view_params);
+                        match view_params::save_named_view(
+                            &renderer.view_params,
+                            &args.views_dir,
+                            &name,
+                        ) {
+                            Ok(()) => println!("Bookmark {slot} saved"),
+                            Err(e) => eprintln!("Failed to save bookmark {slot}: {e}"),
+                        }
+                    } else if let Some(view) = bookmarks[slot] {
+                        renderer.view_params = view;
+                        renderer.update_camera();
+                        changed = true;
+                    } else if let Ok(view) = view_params::load_named_view(&args.views_dir, &name) {
+                        bookmarks[slot] = Some(view);
+                        renderer.view_params = view;
+                        renderer.update_camera();
+                        changed = true;
+                    }
+                }
+            }
+            Event::WindowEvent {
+                event: WindowEvent::Resized(size),
+                ..
+            } => {
+                renderer.resize((size.width, size.height));
+                changed = true;
+            }
+            Event::WindowEvent {
+                event: WindowEvent::ScaleFactorChanged { new_inner_size, .. },
+                ..
+            } => {
+                renderer.resize((new_inner_size.width, new_inner_size.height));
+                changed = true;
             }
             Event::WindowEvent {
                 event: WindowEvent::CloseRequested,
@@ -233,15 +827,48 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 renderer
                     .render(background_shading_enabled, occlusion_shading_enabled)
                     .unwrap();
+                if report_caps {
+                    report_caps = false;
+                    let caps = [
+                        renderer.background_shading_iters,
+                        renderer.occlusion_shading_iters,
+                    ];
+                    let counts = renderer
+                        .filling_counts(background_shading_enabled, occlusion_shading_enabled);
+                    let stages = ["Background", "Occlusion"];
+                    for ((stage, cap), count) in stages.iter().zip(caps).zip(counts) {
+                        if let Some(count) = count {
+                            println!(
+                                "{stage} filling cap {cap}: last iteration changed {count} pixels"
+                            );
+                        }
+                    }
+                }
+                if let Some(stats) = renderer.frame_stats() {
+                    println!("{stats}");
+                    for filling in renderer
+                        .filling_stats(background_shading_enabled, occlusion_shading_enabled)
+                    {
+                        println!(
+                            "{}: {} iterations, {} pixels changed, {} left empty",
+                            filling.stage, filling.iterations, filling.count, filling.empty
+                        );
+                    }
+                }
             }
 
             Event::MainEventsCleared => {
-                renderer
-                    .head_state
-                    .as_ref()
-                    .unwrap()
-                    .window
-                    .request_redraw();
+                // Only render after something changed, otherwise sleep until the next event
+                if changed {
+                    renderer
+                        .head_state
+                        .as_ref()
+                        .unwrap()
+                        .window
+                        .request_redraw();
+                    changed = false;
+                }
+                ctrl.set_wait();
             }
             _ => {}
         });
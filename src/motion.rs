@@ -0,0 +1,263 @@
+use std::f32::consts::PI;
+
+use crate::view_params::{Projection, ViewParams};
+
+// Named "3D photo" camera moves. Amplitude is in scene units for orbit and wiggle (the image spans -1..1),
+// and a fraction of the eye to look_at distance for ken burns and dolly zoom
+#[derive(Copy, Clone, Debug, clap::ValueEnum)]
+pub enum MotionPreset {
+    Orbit,
+    Wiggle,
+    KenBurns,
+    DollyZoom,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Motion {
+    pub preset: MotionPreset,
+    pub duration: f32,
+    pub amplitude: f32,
+    pub fps: f32,
+    // Pull focus from the view's focus depth to this one over the motion
+    pub focus_to: Option<f32>,
+}
+
+// Ease in/out so pushes don't start or stop abruptly
+fn smoothstep(t: f32) -> f32 {
+    t * t * (3.0 - 2.0 * t)
+}
+
+impl Motion {
+    // Orbit and wiggle amplitudes are distances, so any finite one works. Ken burns and dolly zoom ones
+    // have to stay below 1: they would reach the look_at point there, which leaves no image size to zoom
+    // to or field of view to widen to
+    pub fn new(
+        preset: MotionPreset,
+        duration: f32,
+        amplitude: f32,
+        fps: f32,
+        focus_to: Option<f32>,
+    ) -> Result<Motion, Box<dyn std::error::Error>> {
+        if !(duration.is_finite() && duration > 0.0) {
+            return Err(
+                format!("Motion duration {duration} isn't a positive number of seconds").into(),
+            );
+        }
+        if !(fps.is_finite() && fps > 0.0) {
+            return Err(format!("Motion fps {fps} isn't a positive number").into());
+        }
+        let valid = match preset {
+            MotionPreset::Orbit | MotionPreset::Wiggle => amplitude.is_finite() && amplitude >= 0.0,
+            MotionPreset::KenBurns | MotionPreset::DollyZoom => (0.0..1.0).contains(&amplitude),
+        };
+        if !valid {
+            let range = match preset {
+                MotionPreset::Orbit | MotionPreset::Wiggle => "at least 0",
+                MotionPreset::KenBurns | MotionPreset::DollyZoom => "in [0, 1)",
+            };
+            return Err(format!("{preset:?} amplitude {amplitude} isn't {range}").into());
+        }
+        Ok(Motion {
+            preset,
+            duration,
+            amplitude,
+            fps,
+            focus_to,
+        })
+    }
+
+    pub fn frame_count(&self) -> usize {
+        std::cmp::max(1, (self.duration * self.fps).round() as usize)
+    }
+
+    pub fn frames(&self, base: &ViewParams) -> Vec<ViewParams> {
+        let count = self.frame_count();
+        (0..count)
+            .map(|i| {
+                let t = match self.preset {
+                    // Looping presets shouldn't repeat the first frame at the end
+                    MotionPreset::Orbit | MotionPreset::Wiggle => i as f32 / count as f32,
+                    MotionPreset::KenBurns | MotionPreset::DollyZoom => {
+                        i as f32 / std::cmp::max(1, count - 1) as f32
+                    }
+                };
+                self.frame(base, t)
+            })
+            .collect()
+    }
+
+    // Generate view for t in [0, 1]
+    fn frame(&self, base: &ViewParams, t: f32) -> ViewParams {
+        let mut frame = *base;
+        if let Some(focus_to) = self.focus_to {
+            let focus = base.focus_distance();
+            frame.set_focus(Some(focus + (focus_to - focus) * smoothstep(t)));
+        }
+        let eye = base.eye();
+        let look_at = base.look_at();
+        let distance = (look_at - eye).magnitude();
+        let forward = (look_at - eye).normalize();
+        let right = forward.cross(&base.up()).normalize();
+        let up = right.cross(&forward);
+        match self.preset {
+            MotionPreset::Orbit => {
+                let angle = 2.0 * PI * t;
+                frame.set_eye(eye + (right * angle.cos() + up * angle.sin()) * self.amplitude);
+            }
+            MotionPreset::Wiggle => {
+                frame.set_eye(eye + right * (2.0 * PI * t).sin() * self.amplitude);
+            }
+            MotionPreset::KenBurns => {
+                let push = self.amplitude * smoothstep(t);
+                match base.projection() {
+                    // Moving an orthographic camera doesn't change the image size, so zoom instead
+                    Projection::Orthographic { .. } => {
+                        frame.set_projection(base.projection().zoomed(1.0 / (1.0 - push)))
+                    }
+                    Projection::Perspective { .. } | Projection::Frustum { .. } => {
+                        frame.set_eye(eye + forward * distance * push)
+                    }
+                }
+            }
+            MotionPreset::DollyZoom => {
+                // Keep the look_at plane the same size on screen while the camera moves towards it
+                let (half_height, aspect, near, far) = match base.projection() {
+                    Projection::Orthographic {
+                        left,
+                        right,
+                        bottom,
+                        top,
+                        near,
+                        far,
+                    } => (
+                        (top - bottom) / 2.0,
+                        (right - left) / (top - bottom),
+                        near,
+                        far,
+                    ),
+                    Projection::Perspective {
+                        fovy,
+                        aspect,
+                        near,
+                        far,
+                    } => (distance * (fovy / 2.0).tan(), aspect, near, far),
+                    Projection::Frustum {
+                        left,
+                        right,
+                        bottom,
+                        top,
+                        near,
+                        far,
+                    } => (
+                        distance * (top - bottom) / (2.0 * near),
+                        (right - left) / (top - bottom),
+                        near,
+                        far,
+                    ),
+                };
+                let new_distance = distance * (1.0 - self.amplitude * smoothstep(t));
+                frame.set_eye(look_at - forward * new_distance);
+                frame.set_projection(Projection::Perspective {
+                    fovy: 2.0 * (half_height / new_distance).atan(),
+                    aspect,
+                    // Orthographic near plane is 0, which perspective can't use
+                    near: near.max(0.01),
+                    far,
+                });
+            }
+        }
+        frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Point3;
+
+    fn base() -> ViewParams {
+        ViewParams::new(
+            Point3::new(0.0, 0.0, 2.0),
+            Point3::origin(),
+            Projection::Perspective {
+                fovy: PI / 2.0,
+                aspect: 1.0,
+                near: 0.1,
+                far: 10.0,
+            },
+        )
+    }
+
+    fn motion(preset: MotionPreset, amplitude: f32) -> Motion {
+        Motion::new(preset, 1.0, amplitude, 4.0, None).unwrap()
+    }
+
+    #[test]
+    fn amplitude_depends_on_the_preset() {
+        for preset in [MotionPreset::KenBurns, MotionPreset::DollyZoom] {
+            for amplitude in [-0.1, 1.0, 2.0, f32::NAN] {
+                assert!(Motion::new(preset, 1.0, amplitude, 30.0, None).is_err());
+            }
+            assert!(Motion::new(preset, 1.0, 0.0, 30.0, None).is_ok());
+        }
+        for preset in [MotionPreset::Orbit, MotionPreset::Wiggle] {
+            for amplitude in [-0.1, f32::INFINITY, f32::NAN] {
+                assert!(Motion::new(preset, 1.0, amplitude, 30.0, None).is_err());
+            }
+            assert!(Motion::new(preset, 1.0, 2.5, 30.0, None).is_ok());
+        }
+    }
+
+    #[test]
+    fn rejects_empty_timing() {
+        for (duration, fps) in [
+            (0.0, 30.0),
+            (-1.0, 30.0),
+            (f32::NAN, 30.0),
+            (1.0, 0.0),
+            (1.0, f32::INFINITY),
+        ] {
+            assert!(Motion::new(MotionPreset::Wiggle, duration, 0.1, fps, None).is_err());
+        }
+    }
+
+    #[test]
+    fn at_least_one_frame() {
+        let motion = Motion::new(MotionPreset::Wiggle, 0.01, 0.1, 30.0, None).unwrap();
+        assert_eq!(motion.frames(&base()).len(), 1);
+    }
+
+    #[test]
+    fn orbit_loops_without_repeating_the_first_frame() {
+        let frames = motion(MotionPreset::Orbit, 0.5).frames(&base());
+        assert_eq!(frames.len(), 4);
+        let eyes: Vec<Point3<f32>> = frames.iter().map(|frame| frame.eye()).collect();
+        // Quarter turns around the eye, starting to the right and going up
+        let expected = [[0.5, 0.0], [0.0, 0.5], [-0.5, 0.0], [0.0, -0.5]];
+        for (eye, [x, y]) in eyes.iter().zip(expected) {
+            assert!((eye - Point3::new(x, y, 2.0)).magnitude() < 1e-5, "{eye}");
+        }
+    }
+
+    #[test]
+    fn ken_burns_ends_pushed_in() {
+        let frames = motion(MotionPreset::KenBurns, 0.25).frames(&base());
+        assert!((frames[0].eye() - base().eye()).magnitude() < 1e-6);
+        let last = frames.last().unwrap().eye();
+        assert!(
+            (last - Point3::new(0.0, 0.0, 1.5)).magnitude() < 1e-5,
+            "{last}"
+        );
+    }
+
+    #[test]
+    fn dolly_zoom_keeps_the_look_at_plane_size() {
+        for frame in motion(MotionPreset::DollyZoom, 0.5).frames(&base()) {
+            let Projection::Perspective { fovy, .. } = frame.projection() else {
+                panic!("dolly zoom should stay perspective");
+            };
+            let distance = (frame.look_at() - frame.eye()).magnitude();
+            assert!((distance * (fovy / 2.0).tan() - 2.0).abs() < 1e-4);
+        }
+    }
+}
@@ -0,0 +1,268 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use nalgebra::{Matrix4, Quaternion, UnitQuaternion, Vector3};
+
+use crate::transforms::{self, Transforms};
+use crate::view_params::{Projection, ViewParams};
+
+// Datasets don't give clip planes, so imported cameras get these
+const NEAR: f32 = 0.01;
+const FAR: f32 = 100.0;
+
+// COLMAP/OpenCV cameras look down +z with y pointing down, OpenGL looks down -z with y up
+#[rustfmt::skip]
+const OPENCV_TO_OPENGL: Matrix4<f32> = Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, -1.0, 0.0, 0.0,
+    0.0, 0.0, -1.0, 0.0,
+    0.0, 0.0, 0.0, 1.0,
+);
+
+// A camera to render: the output's file name, the view and, for dataset cameras, the image size the
+// intrinsics were given for
+#[derive(Clone)]
+pub struct Pose {
+    pub name: String,
+    pub view_params: ViewParams,
+    pub size: Option<(u32, u32)>,
+}
+
+// Turn a dataset image name into an output file name, e.g. "./train/r_0.png" -> "train_r_0"
+fn frame_name(name: &str) -> String {
+    Path::new(name.trim_start_matches("./"))
+        .with_extension("")
+        .to_string_lossy()
+        .replace(['/', '\\'], "_")
+}
+
+// Load every pose in a NeRF transforms.json, or a COLMAP images.txt with cameras.txt next to it.
+// size is used when the dataset doesn't give image dimensions
+pub fn load_poses(path: &str, size: (u32, u32)) -> Result<Vec<Pose>, Box<dyn std::error::Error>> {
+    if path.ends_with(".json") {
+        load_transforms(path, size)
+    } else {
+        load_colmap(path)
+    }
+}
+
+fn load_transforms(path: &str, size: (u32, u32)) -> Result<Vec<Pose>, Box<dyn std::error::Error>> {
+    let file = std::fs::File::open(path)?;
+    let transforms: Transforms = serde_json::from_reader(std::io::BufReader::new(file))?;
+    let mut poses = Vec::with_capacity(transforms.frames.len());
+    for frame in &transforms.frames {
+        let frame_size = (
+            frame.w.or(transforms.w).unwrap_or(size.0),
+            frame.h.or(transforms.h).unwrap_or(size.1),
+        );
+        let (width, height) = (frame_size.0 as f32, frame_size.1 as f32);
+        let fl_x = frame
+            .fl_x
+            .or(transforms.fl_x)
+            .or(transforms
+                .camera_angle_x
+                .map(|angle| width / 2.0 / (angle / 2.0).tan()))
+            .ok_or_else(|| format!("No intrinsics for {}", frame.file_path))?;
+        let fl_y = frame.fl_y.or(transforms.fl_y).unwrap_or(fl_x);
+        let cx = frame.cx.or(transforms.cx).unwrap_or(width / 2.0);
+        let cy = frame.cy.or(transforms.cy).unwrap_or(height / 2.0);
+        let projection =
+            Projection::from_intrinsics((fl_x, fl_y), (cx, cy), (width, height), NEAR, FAR);
+        let singular = || format!("Singular transform_matrix for {}", frame.file_path);
+        // transform_matrix is already camera-to-world in OpenGL convention
+        let view = transforms::from_rows(&frame.transform_matrix)
+            .try_inverse()
+            .ok_or_else(singular)?;
+        let view_params = ViewParams::from_view_matrix(&view, projection).ok_or_else(singular)?;
+        poses.push(Pose {
+            name: frame_name(&frame.file_path),
+            view_params,
+            size: Some(frame_size),
+        });
+    }
+    Ok(poses)
+}
+
+// Each camera's projection and image size, by camera id
+type Cameras = HashMap<String, (Projection, (u32, u32))>;
+
+fn load_colmap_cameras(path: &Path) -> Result<Cameras, Box<dyn std::error::Error>> {
+    let mut cameras = HashMap::new();
+    // CAMERA_ID MODEL WIDTH HEIGHT PARAMS[]
+    for line in std::fs::read_to_string(path)?.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let params = fields
+            .get(4..)
+            .ok_or_else(|| format!("Invalid camera: {line}"))?
+            .iter()
+            .map(|param| param.parse::<f32>())
+            .collect::<Result<Vec<f32>, _>>()?;
+        let size = (fields[2].parse::<u32>()?, fields[3].parse::<u32>()?);
+        let (width, height) = (size.0 as f32, size.1 as f32);
+        // Distortion parameters are ignored, only the pinhole part of each model is used
+        let (focal, principal) = match (fields[1], &params[..]) {
+            (
+                "SIMPLE_PINHOLE"
+                | "SIMPLE_RADIAL"
+                | "RADIAL"
+                | "SIMPLE_RADIAL_FISHEYE"
+                | "RADIAL_FISHEYE",
+                [f, cx, cy, ..],
+            ) => ((*f, *f), (*cx, *cy)),
+            (_, [fx, fy, cx, cy, ..]) => ((*fx, *fy), (*cx, *cy)),
+            _ => return Err(format!("Unsupported camera: {line}").into()),
+        };
+        cameras.insert(
+            fields[0].to_owned(),
+            (
+                Projection::from_intrinsics(focal, principal, (width, height), NEAR, FAR),
+                size,
+            ),
+        );
+    }
+    Ok(cameras)
+}
+
+fn load_colmap(images_path: &str) -> Result<Vec<Pose>, Box<dyn std::error::Error>> {
+    let images_path = Path::new(images_path);
+    let cameras = load_colmap_cameras(&images_path.with_file_name("cameras.txt"))?;
+    let mut poses = Vec::new();
+    // Each image takes two lines: IMAGE_ID QW QX QY QZ TX TY TZ CAMERA_ID NAME, then its 2D points (possibly empty)
+    for line in std::fs::read_to_string(images_path)?
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .step_by(2)
+    {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 10 {
+            return Err(format!("Invalid image: {line}").into());
+        }
+        let values = fields[1..8]
+            .iter()
+            .map(|value| value.parse::<f32>())
+            .collect::<Result<Vec<f32>, _>>()?;
+        let rotation = UnitQuaternion::from_quaternion(Quaternion::new(
+            values[0], values[1], values[2], values[3],
+        ));
+        // COLMAP stores world-to-camera
+        let mut world_to_camera = rotation.to_homogeneous();
+        world_to_camera
+            .fixed_view_mut::<3, 1>(0, 3)
+            .copy_from(&Vector3::new(values[4], values[5], values[6]));
+        let (projection, size) = *cameras
+            .get(fields[8])
+            .ok_or_else(|| format!("Unknown camera {} in: {line}", fields[8]))?;
+        let view_params =
+            ViewParams::from_view_matrix(&(OPENCV_TO_OPENGL * world_to_camera), projection)
+                .ok_or_else(|| format!("Singular pose: {line}"))?;
+        poses.push(Pose {
+            name: frame_name(&fields[9..].join(" ")),
+            view_params,
+            size: Some(size),
+        });
+    }
+    Ok(poses)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Point3;
+
+    // A fresh directory per test, since tests run in parallel
+    fn dataset(test: &str, files: &[(&str, &str)]) -> std::path::PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("pointcloudrender-{test}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, contents) in files {
+            std::fs::write(dir.join(name), contents).unwrap();
+        }
+        dir
+    }
+
+    fn assert_near(a: Point3<f32>, b: Point3<f32>) {
+        assert!((a - b).magnitude() < 1e-5, "{a} != {b}");
+    }
+
+    #[test]
+    fn colmap_takes_every_other_line() {
+        let dir = dataset(
+            "colmap",
+            &[
+                (
+                    "cameras.txt",
+                    "# CAMERA_ID MODEL WIDTH HEIGHT PARAMS[]\n\
+                     1 PINHOLE 640 480 500 500 320 240\n\
+                     2 SIMPLE_RADIAL 320 240 300 160 120 0.1\n",
+                ),
+                (
+                    "images.txt",
+                    "# IMAGE_ID QW QX QY QZ TX TY TZ CAMERA_ID NAME\n\
+                     # POINTS2D[] as (X, Y, POINT3D_ID)\n\
+                     1 1 0 0 0 0 0 0 1 images/a.png\n\
+                     100.0 200.0 5 300.0 400.0 -1\n\
+                     2 1 0 0 0 1 2 3 2 images/frame 2.jpg\n\
+                     \n",
+                ),
+            ],
+        );
+        let poses = load_poses(dir.join("images.txt").to_str().unwrap(), (1, 1)).unwrap();
+        assert_eq!(poses.len(), 2);
+        assert_eq!(poses[0].name, "images_a");
+        assert_eq!(poses[1].name, "images_frame 2");
+        assert_eq!(poses[0].size, Some((640, 480)));
+        assert_eq!(poses[1].size, Some((320, 240)));
+        // Identity rotation looks down +z in COLMAP, and the eye is at -R^T t
+        assert_near(poses[0].view_params.eye(), Point3::origin());
+        assert_near(poses[0].view_params.look_at(), Point3::new(0.0, 0.0, 1.0));
+        assert_near(poses[1].view_params.eye(), Point3::new(-1.0, -2.0, -3.0));
+        let Projection::Frustum { left, right, .. } = poses[1].view_params.projection() else {
+            panic!("expected a frustum");
+        };
+        assert!((left + 160.0 / 300.0 * NEAR).abs() < 1e-7);
+        assert!((right - 160.0 / 300.0 * NEAR).abs() < 1e-7);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn transforms_sizes_fall_back() {
+        let dir = dataset(
+            "transforms",
+            &[(
+                "transforms.json",
+                r#"{"camera_angle_x": 1.0, "w": 800, "frames": [
+                    {"file_path": "./train/r_0.png", "transform_matrix":
+                        [[1, 0, 0, 0], [0, 1, 0, 0], [0, 0, 1, 4], [0, 0, 0, 1]]},
+                    {"file_path": "./train/r_1", "w": 400, "h": 300, "transform_matrix":
+                        [[1, 0, 0, 0], [0, 1, 0, 0], [0, 0, 1, 0], [0, 0, 0, 1]]}
+                ]}"#,
+            )],
+        );
+        let poses = load_poses(dir.join("transforms.json").to_str().unwrap(), (640, 480)).unwrap();
+        assert_eq!(poses[0].name, "train_r_0");
+        // Width from the file, height from the fallback size, each frame's own size over both
+        assert_eq!(poses[0].size, Some((800, 480)));
+        assert_eq!(poses[1].size, Some((400, 300)));
+        assert_near(poses[0].view_params.eye(), Point3::new(0.0, 0.0, 4.0));
+        assert_near(poses[0].view_params.look_at(), Point3::new(0.0, 0.0, 3.0));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn transforms_need_intrinsics() {
+        let dir = dataset(
+            "no-intrinsics",
+            &[(
+                "transforms.json",
+                r#"{"frames": [{"file_path": "a.png", "transform_matrix":
+                    [[1, 0, 0, 0], [0, 1, 0, 0], [0, 0, 1, 0], [0, 0, 0, 1]]}]}"#,
+            )],
+        );
+        assert!(load_poses(dir.join("transforms.json").to_str().unwrap(), (640, 480)).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
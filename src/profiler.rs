@@ -0,0 +1,137 @@
+use serde::Serialize;
+
+// Enough for a stereo frame with every stage on, with room to spare
+const MAX_TIMESTAMPS: u32 = 64;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct StageTime {
+    pub stage: String,
+    pub ms: f64,
+}
+
+// GPU time of each stage in the last profiled frame. Stages that ran more than once (e.g. once per
+// stereo eye) are summed
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct FrameStats {
+    pub stages: Vec<StageTime>,
+    pub total_ms: f64,
+}
+
+impl std::fmt::Display for FrameStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for stage in &self.stages {
+            write!(f, "{} {:.3}ms, ", stage.stage, stage.ms)?;
+        }
+        write!(f, "total {:.3}ms", self.total_ms)
+    }
+}
+
+// Writes timestamp queries between render stages. Each timestamp closes the stage that started at the
+// previous one, except marks, which only start the next stage. Results are resolved at the end of the
+// frame and read back after it's submitted, which stalls until the GPU is done
+pub struct Profiler {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    read_buffer: wgpu::Buffer,
+    // Stage closed by each timestamp written this frame, None for marks
+    labels: Vec<Option<String>>,
+    period: f32,
+    last_frame: FrameStats,
+}
+
+impl Profiler {
+    // Needs wgpu::Features::TIMESTAMP_QUERY and TIMESTAMP_QUERY_INSIDE_ENCODERS
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let size = MAX_TIMESTAMPS as u64 * std::mem::size_of::<u64>() as u64;
+        Profiler {
+            query_set: device.create_query_set(&wgpu::QuerySetDescriptor {
+                label: Some("Stage Timestamps"),
+                ty: wgpu::QueryType::Timestamp,
+                count: MAX_TIMESTAMPS,
+            }),
+            resolve_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Timestamp Resolve"),
+                size,
+                usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            }),
+            read_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Timestamp Readback"),
+                size,
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            }),
+            labels: Vec::new(),
+            period: queue.get_timestamp_period(),
+            last_frame: FrameStats::default(),
+        }
+    }
+
+    fn write(&mut self, command_encoder: &mut wgpu::CommandEncoder, label: Option<String>) {
+        // Extra timestamps are dropped rather than overflowing the query set
+        if self.labels.len() < MAX_TIMESTAMPS as usize {
+            command_encoder.write_timestamp(&self.query_set, self.labels.len() as u32);
+            self.labels.push(label);
+        }
+    }
+
+    // Start of a stage, not counting the time since the previous timestamp
+    pub fn mark(&mut self, command_encoder: &mut wgpu::CommandEncoder) {
+        self.write(command_encoder, None);
+    }
+
+    // End of the stage called label
+    pub fn timestamp(&mut self, command_encoder: &mut wgpu::CommandEncoder, label: &str) {
+        self.write(command_encoder, Some(label.to_string()));
+    }
+
+    // Copy this frame's timestamps somewhere they can be read. Goes in the frame's last encoder
+    pub fn resolve(&self, command_encoder: &mut wgpu::CommandEncoder) {
+        if self.labels.is_empty() {
+            return;
+        }
+        let count = self.labels.len() as u32;
+        command_encoder.resolve_query_set(&self.query_set, 0..count, &self.resolve_buffer, 0);
+        command_encoder.copy_buffer_to_buffer(
+            &self.resolve_buffer,
+            0,
+            &self.read_buffer,
+            0,
+            count as u64 * std::mem::size_of::<u64>() as u64,
+        );
+    }
+
+    // Read back the resolved timestamps once the frame has been submitted, and start a new frame
+    pub fn finish_frame(&mut self, device: &wgpu::Device) -> &FrameStats {
+        if self.labels.is_empty() {
+            return &self.last_frame;
+        }
+        let slice = self.read_buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |r| r.unwrap());
+        device.poll(wgpu::Maintain::Wait);
+        let ticks: Vec<u64> = bytemuck::cast_slice(&slice.get_mapped_range()[..]).to_vec();
+        self.read_buffer.unmap();
+
+        let to_ms = |ticks: u64| ticks as f64 * self.period as f64 / 1e6;
+        let mut stats = FrameStats::default();
+        for (i, label) in self.labels.iter().enumerate().skip(1) {
+            let Some(label) = label else { continue };
+            let ms = to_ms(ticks[i].saturating_sub(ticks[i - 1]));
+            match stats.stages.iter_mut().find(|stage| &stage.stage == label) {
+                Some(stage) => stage.ms += ms,
+                None => stats.stages.push(StageTime {
+                    stage: label.clone(),
+                    ms,
+                }),
+            }
+        }
+        stats.total_ms = to_ms(ticks[self.labels.len() - 1].saturating_sub(ticks[0]));
+        self.labels.clear();
+        self.last_frame = stats;
+        &self.last_frame
+    }
+
+    pub fn last_frame(&self) -> &FrameStats {
+        &self.last_frame
+    }
+}
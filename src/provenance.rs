@@ -0,0 +1,125 @@
+use crate::texture::Texture;
+
+// Records where each output pixel came from, for inpainting the invented ones later.
+// Values in the red channel are 0 (original point), 1/3 (background filling), 2/3 (occlusion filling)
+// and 1 (still empty)
+pub struct ProvenanceMask {
+    pub texture: Texture,
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::ComputePipeline,
+}
+
+impl ProvenanceMask {
+    pub fn new(device: &wgpu::Device, dims: (u32, u32)) -> Self {
+        let input_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Provenance Bindgroup Layout"),
+            entries: &[
+                input_entry(0),
+                input_entry(1),
+                input_entry(2),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: wgpu::TextureFormat::Rgba8Unorm,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                input_entry(4),
+            ],
+        });
+
+        let shader = device.create_shader_module(wgpu::include_wgsl!("shaders/provenance.wgsl"));
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("provenance_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("provenance"),
+            module: &shader,
+            layout: Some(&layout),
+            entry_point: "main",
+            compilation_options: Default::default(),
+            cache: None,
+        });
+
+        ProvenanceMask {
+            texture: ProvenanceMask::create_texture(device, dims),
+            bind_group_layout,
+            pipeline,
+        }
+    }
+
+    fn create_texture(device: &wgpu::Device, dims: (u32, u32)) -> Texture {
+        Texture::new(
+            device,
+            dims,
+            wgpu::TextureFormat::Rgba8Unorm,
+            wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC,
+            "Provenance Mask",
+        )
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, dims: (u32, u32)) {
+        self.texture = ProvenanceMask::create_texture(device, dims);
+    }
+
+    // Depth textures from after rasterizing, after background filling and at the end of the frame,
+    // plus the final color
+    pub fn run(
+        &self,
+        device: &wgpu::Device,
+        command_encoder: &mut wgpu::CommandEncoder,
+        raster_depth: &Texture,
+        background_depth: &Texture,
+        (final_image, final_depth): (&Texture, &Texture),
+    ) {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Provenance Bindgroup"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&raster_depth.texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&background_depth.texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&final_depth.texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&self.texture.texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&final_image.texture_view),
+                },
+            ],
+        });
+        let dims = (self.texture.texture.width(), self.texture.texture.height());
+        let mut compute_pass = command_encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("provenance_pass"),
+            ..Default::default()
+        });
+        compute_pass.set_pipeline(&self.pipeline);
+        compute_pass.set_bind_group(0, &bind_group, &[]);
+        compute_pass.dispatch_workgroups(dims.0.div_ceil(8), dims.1.div_ceil(8), 1);
+    }
+}
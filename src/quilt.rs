@@ -0,0 +1,159 @@
+use image::{imageops, ImageBuffer, Rgba};
+
+// Layout of a light-field "quilt": views are tiled left to right, starting from the bottom row,
+// with view 0 the leftmost camera of the sweep
+#[derive(Copy, Clone, Debug)]
+pub struct Quilt {
+    pub views: u32,
+    pub columns: u32,
+    pub rows: u32,
+    // Total horizontal angle covered by the camera sweep, in degrees
+    pub view_cone: f32,
+    // Size of the whole quilt image
+    pub width: u32,
+    pub height: u32,
+}
+
+// Every view is a full render, and the quilt is assembled in memory, so keep both to what displays use
+const MAX_VIEWS: u32 = 256;
+const MAX_DIMENSION: u32 = 16384;
+
+impl Quilt {
+    // Checks the layout has at least one tile of at least one pixel, with a tile for every view, and that
+    // the view count and image size are within the caps above
+    pub fn new(
+        views: u32,
+        columns: u32,
+        rows: u32,
+        view_cone: f32,
+        width: u32,
+        height: u32,
+    ) -> Result<Quilt, Box<dyn std::error::Error>> {
+        if views == 0 || views > MAX_VIEWS {
+            return Err(format!("A quilt needs 1 to {MAX_VIEWS} views, not {views}").into());
+        }
+        if width == 0 || height == 0 || width > MAX_DIMENSION || height > MAX_DIMENSION {
+            return Err(format!(
+                "A {width}x{height} quilt isn't between 1x1 and {MAX_DIMENSION}x{MAX_DIMENSION}"
+            )
+            .into());
+        }
+        (width as usize)
+            .checked_mul(height as usize)
+            .and_then(|pixels| pixels.checked_mul(4))
+            .ok_or_else(|| format!("A {width}x{height} quilt doesn't fit in memory"))?;
+        if columns == 0 || rows == 0 {
+            return Err("A quilt needs at least one column and one row".into());
+        }
+        if u64::from(views) > u64::from(columns) * u64::from(rows) {
+            return Err(format!("{views} views don't fit in {columns}x{rows} tiles").into());
+        }
+        if width < columns || height < rows {
+            return Err(format!(
+                "A {width}x{height} quilt is too small for {columns}x{rows} tiles"
+            )
+            .into());
+        }
+        Ok(Quilt {
+            views,
+            columns,
+            rows,
+            view_cone,
+            width,
+            height,
+        })
+    }
+
+    pub fn tile_size(&self) -> (u32, u32) {
+        (self.width / self.columns, self.height / self.rows)
+    }
+
+    // Sideways camera offset for each view so the sweep covers view_cone at the convergence distance
+    pub fn offsets(&self, convergence: f32) -> Vec<f32> {
+        let half_cone = self.view_cone.to_radians() / 2.0;
+        (0..self.views)
+            .map(|i| {
+                let t = if self.views > 1 {
+                    i as f32 / (self.views - 1) as f32 * 2.0 - 1.0
+                } else {
+                    0.0
+                };
+                convergence * (t * half_cone).tan()
+            })
+            .collect()
+    }
+
+    pub fn place(
+        &self,
+        quilt: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
+        view: usize,
+        image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    ) {
+        let (tile_width, tile_height) = self.tile_size();
+        let tile = imageops::resize(
+            image,
+            tile_width,
+            tile_height,
+            imageops::FilterType::Triangle,
+        );
+        let column = view as u32 % self.columns;
+        let row_from_bottom = view as u32 / self.columns;
+        imageops::replace(
+            quilt,
+            &tile,
+            (column * tile_width) as i64,
+            ((self.rows - 1 - row_from_bottom) * tile_height) as i64,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_layouts_without_room() {
+        assert!(Quilt::new(1, 0, 1, 40.0, 100, 100).is_err());
+        assert!(Quilt::new(1, 1, 0, 40.0, 100, 100).is_err());
+        assert!(Quilt::new(7, 3, 2, 40.0, 300, 200).is_err());
+        assert!(Quilt::new(1, 8, 6, 40.0, 7, 100).is_err());
+        assert!(Quilt::new(0, 1, 1, 40.0, 100, 100).is_err());
+        assert!(Quilt::new(257, 16, 17, 40.0, 1600, 1700).is_err());
+        assert!(Quilt::new(1, 1, 1, 40.0, 16385, 100).is_err());
+        assert!(Quilt::new(u32::MAX, u32::MAX, u32::MAX, 40.0, u32::MAX, u32::MAX).is_err());
+        assert!(Quilt::new(256, 16, 16, 40.0, 16384, 16384).is_ok());
+    }
+
+    #[test]
+    fn tiles_round_down() {
+        let quilt = Quilt::new(48, 8, 6, 40.0, 4100, 4097).unwrap();
+        assert_eq!(quilt.tile_size(), (512, 682));
+    }
+
+    #[test]
+    fn offsets_sweep_the_cone() {
+        let quilt = Quilt::new(3, 3, 1, 90.0, 30, 10).unwrap();
+        let offsets = quilt.offsets(2.0);
+        assert!((offsets[0] + 2.0).abs() < 1e-5);
+        assert!(offsets[1].abs() < 1e-6);
+        assert!((offsets[2] - 2.0).abs() < 1e-5);
+        let single = Quilt::new(1, 1, 1, 90.0, 10, 10).unwrap();
+        assert_eq!(single.offsets(2.0), vec![0.0]);
+    }
+
+    #[test]
+    fn views_fill_from_the_bottom_left() {
+        let quilt = Quilt::new(4, 2, 2, 40.0, 4, 4).unwrap();
+        let mut image = ImageBuffer::new(4, 4);
+        for view in 0..4u8 {
+            quilt.place(
+                &mut image,
+                view as usize,
+                &ImageBuffer::from_pixel(2, 2, Rgba([view, 0, 0, 255])),
+            );
+        }
+        // Top left, top right, bottom left, bottom right
+        let corners = [(0, 0), (3, 0), (0, 3), (3, 3)].map(|(x, y)| image.get_pixel(x, y).0[0]);
+        assert_eq!(corners, [2, 3, 0, 1]);
+    }
+}
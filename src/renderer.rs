@@ -1,12 +1,31 @@
 use image::{ImageBuffer, Luma, Rgba};
-use nalgebra::{Matrix4, Point3};
-use wgpu::{include_wgsl, util::DeviceExt};
+use nalgebra::{Matrix4, Point3, Vector3, Vector4};
+use wgpu::util::DeviceExt;
 
-use crate::{filling_shader::FillingShader, texture::Texture, view_params::ViewParams};
+use crate::{
+    adapter::{self, AdapterOptions, Capabilities},
+    background::Background,
+    compute_raster::{ComputeRasterizer, RasterMode},
+    debug_view::{DebugView, DisplayMode},
+    depth_of_field::{DepthOfField, DepthOfFieldPass},
+    downsample::Downsampler,
+    equirect::{self, InputProjection},
+    filling_shader::{FillingShader, FillingStats},
+    layer::Layer,
+    poses::Pose,
+    profiler::{FrameStats, Profiler},
+    provenance::ProvenanceMask,
+    quilt::Quilt,
+    stereo::{Stereo, StereoCompositor},
+    texture::Texture,
+    transforms::TransformsFile,
+    view_params::{Projection, ViewParams},
+    vr::{self, Vr},
+};
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
-struct Vertex {
+pub struct Vertex {
     position: [f32; 3],
     color: [f32; 4],
 }
@@ -23,37 +42,54 @@ impl Vertex {
     }
 }
 
-// TODO: refactor into view_params.rs
-#[rustfmt::skip]
-pub const OPENGL_TO_WGPU_MATRIX: Matrix4<f32> = Matrix4::new(
-    1.0, 0.0, 0.0, 0.0,
-    0.0, 1.0, 0.0, 0.0,
-    0.0, 0.0, 0.5, 0.5,
-    0.0, 0.0, 0.0, 1.0,
-);
+pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+// What gets used in the depth texture used for compute shading
+pub const DEPTH_STORAGE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Float;
+// Everything up to the final resolve works on linear color, with enough precision that filling
+// and downsampling don't band
+pub const WORKING_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
 
-#[repr(C)]
-#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
-struct ViewUniform {
-    view: [f32; 16],
+// Input images are sRGB encoded, shading and filling happen in linear space
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
 }
-impl From<ViewParams> for ViewUniform {
-    fn from(view_params: ViewParams) -> ViewUniform {
-        let matrix = OPENGL_TO_WGPU_MATRIX * view_params.projection * view_params.camera;
-        ViewUniform {
-            view: matrix.as_slice().try_into().unwrap(),
+
+// How the input is placed in an output with a different aspect ratio
+#[derive(Copy, Clone, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum AspectMode {
+    // Show the whole image, letterboxed
+    Fit,
+    // Cover the whole output, cropping the image
+    Fill,
+    // Cover the whole output, distorting the image
+    Stretch,
+}
+
+impl AspectMode {
+    // x/y scale applied to the projection to get this placement
+    fn scale(&self, input_size: (u32, u32), output_size: (u32, u32)) -> [f32; 2] {
+        let input_aspect = input_size.0 as f32 / input_size.1 as f32;
+        let output_aspect = output_size.0 as f32 / output_size.1 as f32;
+        let wider = output_aspect > input_aspect;
+        match self {
+            AspectMode::Fit if wider => [input_aspect / output_aspect, 1.0],
+            AspectMode::Fit => [1.0, output_aspect / input_aspect],
+            AspectMode::Fill if wider => [1.0, output_aspect / input_aspect],
+            AspectMode::Fill => [input_aspect / output_aspect, 1.0],
+            AspectMode::Stretch => [1.0, 1.0],
         }
     }
 }
 
-pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
-// What gets used in the depth texture used for compute shading
-pub const DEPTH_STORAGE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Float;
-
 // Describes state of window (and surface)
 pub struct HeadState {
+    // Declared before the window so it's dropped first
+    pub surface: wgpu::Surface<'static>,
     pub window: winit::window::Window,
-    pub surface: wgpu::Surface,
     surface_config: wgpu::SurfaceConfiguration,
 }
 
@@ -62,16 +98,41 @@ impl HeadState {
         device: &wgpu::Device,
         adapter: &wgpu::Adapter,
         window: winit::window::Window,
-        surface: wgpu::Surface,
-    ) -> Self {
+        surface: wgpu::Surface<'static>,
+        capabilities: &mut Capabilities,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
         let size = window.inner_size();
-        let surface_caps = surface.get_capabilities(&adapter);
-        let surface_format = surface_caps
-            .formats
-            .iter()
-            .copied()
-            .find(|f| !dbg!(f).is_srgb()) // TODO: to srgb or not to srgb?
-            .unwrap_or(surface_caps.formats[0]);
+        let surface_caps = surface.get_capabilities(adapter);
+        // The output is copied onto the surface, which only works between formats that differ by their sRGB
+        // suffix. The output is already sRGB encoded, so the sRGB variant is preferred, which makes the
+        // compositor treat it as sRGB without converting it again
+        let compatible = |format: wgpu::TextureFormat| {
+            surface_caps
+                .formats
+                .iter()
+                .copied()
+                .filter(|f| f.remove_srgb_suffix() == format)
+                .max_by_key(|f| f.is_srgb())
+        };
+        // Rgba8Unorm can always be written from a shader, so a surface without the adapter's preferred output
+        // format can still take an Rgba8Unorm output
+        let (output_format, surface_format) =
+            [capabilities.output_format, wgpu::TextureFormat::Rgba8Unorm]
+                .into_iter()
+                .find_map(|format| Some((format, compatible(format)?)))
+                .ok_or_else(|| {
+                    format!(
+                        "The window surface can't take a copy of the output: it offers {:?}, but the output is {:?} or Rgba8Unorm",
+                        surface_caps.formats, capabilities.output_format
+                    )
+                })?;
+        if output_format != capabilities.output_format {
+            let degraded = "window surface without BGRA8: output is Rgba8Unorm";
+            eprintln!("Degraded: {degraded}");
+            capabilities.degraded.push(degraded);
+            capabilities.output_format = output_format;
+        }
+        eprintln!("Window surface format {surface_format:?}");
         let present_mode = surface_caps
             .present_modes
             .iter()
@@ -87,37 +148,69 @@ impl HeadState {
             width: size.width,
             height: size.height,
             present_mode,
-            alpha_mode: dbg!(surface_caps.alpha_modes[0]),
+            alpha_mode: surface_caps.alpha_modes[0],
+            desired_maximum_frame_latency: 2,
             // TODO: probably should request an RGBA image
             view_formats: vec![],
         };
-        surface.configure(&device, &surface_config);
+        surface.configure(device, &surface_config);
 
-        dbg!(surface_format);
-
-        HeadState {
+        Ok(HeadState {
             window,
             surface,
             surface_config,
-        }
+        })
     }
 }
 
 pub struct Renderer {
     device: wgpu::Device,
     queue: wgpu::Queue,
-    vertex_buffer: wgpu::Buffer,
-    camera_buffer: wgpu::Buffer,
-    camera_bind_group: wgpu::BindGroup,
+    camera_bind_group_layout: wgpu::BindGroupLayout,
+    // Drawn in order into the same targets. Never empty, layer 0 is the scene passed to new or load_scene
+    pub layers: Vec<Layer>,
     target_texture: Texture,
     // Target depth is what we render depth to and use in any compute shaders. Depth_texture is what's used for depth testing
     target_depth: Texture,
     depth_texture: Texture,
     render_pipeline: wgpu::RenderPipeline,
+    pub raster_mode: RasterMode,
+    // Created on the first compute raster render, and again after every resize
+    compute_rasterizer: Option<ComputeRasterizer>,
     background_shader: Option<FillingShader>,
     occlusion_shader: Option<FillingShader>,
+    stereo_compositor: Option<StereoCompositor>,
+    // Created on the first render with depth of field on, and again after every resize
+    depth_of_field_pass: Option<DepthOfFieldPass>,
+    // Blurs the filled image away from the view's focus plane when set
+    pub depth_of_field: Option<DepthOfField>,
+    pub display_mode: DisplayMode,
+    // Created on the first render in a depth, hole or normal mode, and again after every resize
+    debug_view: Option<DebugView>,
+    // Holds the final output-sized image and depth, so this is what gets presented and read back
+    downsampler: Downsampler,
+    // Created the first time a mask is read
+    provenance_mask: Option<ProvenanceMask>,
+    capabilities: Capabilities,
+    // Set while profiling, when the adapter supports timestamp queries
+    profiler: Option<Profiler>,
+    // Which filling shaders the last frame used, so the provenance mask knows which stages ran
+    last_filling: (bool, bool),
+    // Everything before the downsampler renders at supersampling times the output size
+    supersampling: u32,
     pub view_params: ViewParams,
+    pub stereo: Option<Stereo>,
+    input_size: (u32, u32),
+    input_projection: InputProjection,
+    aspect_mode: AspectMode,
+    // Scale applied to every projection to place the input in the output according to aspect_mode
+    viewport_scale: [f32; 2],
+    // When set, screenshots are stereo equirect images instead of the current view
+    pub vr: Option<Vr>,
+    // When set, screenshots also save their provenance mask
+    pub save_mask: bool,
     pub head_state: Option<HeadState>,
+    // Iteration caps, filling stops earlier once an iteration changes nothing
     pub background_shading_iters: u32,
     pub occlusion_shading_iters: u32,
 }
@@ -127,45 +220,40 @@ impl Renderer {
         window: Option<winit::window::Window>,
         image: ImageBuffer<Rgba<u8>, Vec<u8>>,
         depth: ImageBuffer<Luma<u8>, Vec<u8>>,
+        input_projection: InputProjection,
+        adapter_options: &AdapterOptions,
         background_filling: bool,
         occlusion_filling: bool,
-    ) -> Self {
-        let size = image.dimensions();
-
-        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::VULKAN,
-            dx12_shader_compiler: Default::default(),
-            flags: wgpu::InstanceFlags::from_build_config(), // bless wgpu for adding this feature
-            gles_minor_version: wgpu::Gles3MinorVersion::Automatic,
-        });
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let input_size = image.dimensions();
+
+        let instance = adapter::create_instance(adapter_options);
 
         let surface = window
             .as_ref()
-            .map(|window| unsafe { instance.create_surface(&window).unwrap() });
+            .map(|window| adapter::create_surface(&instance, window))
+            .transpose()?;
 
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::HighPerformance,
-                compatible_surface: surface.as_ref(),
-                force_fallback_adapter: false,
-            })
-            .await
-            .unwrap();
+        let adapter =
+            adapter::request_adapter(&instance, adapter_options, surface.as_ref()).await?;
+        eprintln!("Using adapter {}", adapter::describe(&adapter.get_info()));
+        let mut capabilities = Capabilities::probe(&adapter)?;
+        for degraded in &capabilities.degraded {
+            eprintln!("Degraded: {degraded}");
+        }
 
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
-                    features: wgpu::Features::POLYGON_MODE_POINT
-                        | wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
-                        | wgpu::Features::BGRA8UNORM_STORAGE
-                        | wgpu::Features::MAPPABLE_PRIMARY_BUFFERS,
-                    limits: wgpu::Limits::default(),
+                    required_features: capabilities.features,
+                    required_limits: capabilities.limits.clone(),
+                    memory_hints: Default::default(),
                     label: None,
                 },
                 None,
             )
             .await
-            .unwrap();
+            .map_err(|e| format!("Couldn't open {}: {e}", adapter.get_info().name))?;
 
         let head_state = if let Some(window) = window {
             Some(HeadState::from_surface(
@@ -173,67 +261,46 @@ impl Renderer {
                 &adapter,
                 window,
                 surface.unwrap(),
-            ))
+                &mut capabilities,
+            )?)
         } else {
             None
         };
 
-        let texture_format = head_state
+        // Render at the window's size, or the input's when headless
+        let size = head_state
             .as_ref()
-            .map(|hs| hs.surface_config.format)
-            .unwrap_or(wgpu::TextureFormat::Bgra8Unorm);
+            .map(|hs| (hs.surface_config.width, hs.surface_config.height))
+            .unwrap_or(input_size);
 
         // Generate buffers and other on-device resources
-        let vertex_buffer = Renderer::load_image(&device, &image, &depth);
-        let (view_params, camera_buffer) = Renderer::create_camera_buffer(&device);
+        let vertex_buffer = Renderer::load_image(&device, &image, &depth, input_projection);
+        let view_params = Renderer::initial_view(input_size, input_projection);
         let now = std::time::Instant::now();
-        let (camera_bind_group, render_pipeline) = Renderer::create_pipeline(
+        let (camera_bind_group_layout, render_pipeline) =
+            Renderer::create_pipeline(&device, WORKING_FORMAT, DEPTH_STORAGE_FORMAT);
+        let layers = vec![Layer::new(
             &device,
-            &camera_buffer,
-            texture_format,
-            DEPTH_STORAGE_FORMAT,
-        );
+            &camera_bind_group_layout,
+            "scene",
+            vertex_buffer,
+        )];
 
         eprintln!(
             "Time to create render pipeline: {:?}",
             std::time::Instant::now() - now
         );
 
-        let target_texture = Texture::new(
-            &device,
-            size,
-            texture_format,
-            wgpu::TextureUsages::RENDER_ATTACHMENT
-                | wgpu::TextureUsages::STORAGE_BINDING
-                | wgpu::TextureUsages::COPY_SRC
-                | wgpu::TextureUsages::COPY_DST,
-            "Render Buffer",
-        );
-
-        let target_depth = Texture::new(
-            &device,
-            size,
-            DEPTH_STORAGE_FORMAT,
-            wgpu::TextureUsages::RENDER_ATTACHMENT
-                | wgpu::TextureUsages::STORAGE_BINDING
-                | wgpu::TextureUsages::COPY_SRC
-                | wgpu::TextureUsages::COPY_DST,
-            "Depth Render/Compute Buffer",
-        );
-
-        let depth_texture = Texture::new(
-            &device,
-            size,
-            DEPTH_FORMAT,
-            wgpu::TextureUsages::RENDER_ATTACHMENT,
-            "Depth Buffer",
-        );
+        let (target_texture, target_depth, depth_texture) = Renderer::create_targets(&device, size);
+        let downsampler = Downsampler::new(&device, size, capabilities.output_format);
 
         let background_shader = if background_filling {
             Some(FillingShader::new(
                 &device,
                 size,
                 wgpu::include_wgsl!("shaders/background_shader.wgsl"),
+                "background_filling",
+                capabilities.indirect_dispatch,
             ))
         } else {
             None
@@ -244,34 +311,303 @@ impl Renderer {
                 &device,
                 size,
                 wgpu::include_wgsl!("shaders/occlusion_shader.wgsl"),
+                "occlusion_filling",
+                capabilities.indirect_dispatch,
             ))
         } else {
             None
         };
 
-        Renderer {
+        // Converged iterations are skipped with indirect dispatch, without it every iteration up to the cap
+        // runs, so keep the cap at what a fixed iteration count used to be
+        let background_shading_iters = if capabilities.indirect_dispatch {
+            64
+        } else {
+            5
+        };
+
+        let mut renderer = Renderer {
             device,
             queue,
-            vertex_buffer,
-            camera_buffer,
-            camera_bind_group,
+            camera_bind_group_layout,
+            layers,
             target_texture,
             target_depth,
             depth_texture,
             view_params,
             render_pipeline,
+            raster_mode: RasterMode::Hardware,
+            compute_rasterizer: None,
             background_shader,
             occlusion_shader,
+            stereo_compositor: None,
+            depth_of_field_pass: None,
+            depth_of_field: None,
+            display_mode: DisplayMode::Color,
+            debug_view: None,
+            downsampler,
+            provenance_mask: None,
+            capabilities,
+            profiler: None,
+            last_filling: (false, false),
+            supersampling: 1,
+            stereo: None,
+            input_size,
+            input_projection,
+            aspect_mode: AspectMode::Fit,
+            viewport_scale: AspectMode::Fit.scale(input_size, size),
+            vr: None,
+            save_mask: false,
             head_state,
-            background_shading_iters: 5,
+            background_shading_iters,
             occlusion_shading_iters: 1,
+        };
+        // Nothing has been written to the layer uniforms yet
+        renderer.update_camera();
+        Ok(renderer)
+    }
+
+    fn create_targets(device: &wgpu::Device, size: (u32, u32)) -> (Texture, Texture, Texture) {
+        let target_texture = Texture::new(
+            device,
+            size,
+            WORKING_FORMAT,
+            wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::STORAGE_BINDING
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC
+                | wgpu::TextureUsages::COPY_DST,
+            "Render Buffer",
+        );
+
+        let target_depth = Texture::new(
+            device,
+            size,
+            DEPTH_STORAGE_FORMAT,
+            wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::STORAGE_BINDING
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC
+                | wgpu::TextureUsages::COPY_DST,
+            "Depth Render/Compute Buffer",
+        );
+
+        let depth_texture = Texture::new(
+            device,
+            size,
+            DEPTH_FORMAT,
+            wgpu::TextureUsages::RENDER_ATTACHMENT,
+            "Depth Buffer",
+        );
+        (target_texture, target_depth, depth_texture)
+    }
+
+    // Sizes render targets can be created at: neither side empty, nor over the device's texture size limit.
+    // Supersampling is lowered to fit instead of being checked
+    pub fn check_size(
+        &self,
+        (width, height): (u32, u32),
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let max = self.device.limits().max_texture_dimension_2d;
+        if width == 0 || height == 0 {
+            return Err(format!("Can't render at {width}x{height}").into());
+        }
+        if width > max || height > max {
+            return Err(format!(
+                "Can't render at {width}x{height}, this device's textures are at most {max}x{max}"
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    // resize for sizes that come from the user rather than the window
+    pub fn set_size(&mut self, size: (u32, u32)) -> Result<(), Box<dyn std::error::Error>> {
+        self.check_size(size)?;
+        self.resize(size);
+        Ok(())
+    }
+
+    // Reallocate every render target at a new output size, and reconfigure the window surface if there is one
+    pub fn resize(&mut self, size: (u32, u32)) {
+        if size.0 == 0 || size.1 == 0 || size == self.size() {
+            return;
+        }
+        // The supersampled targets have to fit within the texture size limit too
+        let max_factor = self.device.limits().max_texture_dimension_2d / size.0.max(size.1);
+        self.supersampling = self.supersampling.min(max_factor.max(1));
+        if let Some(head_state) = &mut self.head_state {
+            head_state.surface_config.width = size.0;
+            head_state.surface_config.height = size.1;
+            head_state
+                .surface
+                .configure(&self.device, &head_state.surface_config);
+        }
+        self.downsampler.resize(&self.device, size);
+        if let Some(provenance_mask) = &mut self.provenance_mask {
+            provenance_mask.resize(&self.device, size);
+        }
+        self.resize_internal();
+        self.viewport_scale = self.aspect_mode.scale(self.input_size, size);
+        self.update_camera();
+    }
+
+    // Reallocate the supersampled targets to match the output size and supersampling factor
+    fn resize_internal(&mut self) {
+        let size = self.internal_size();
+        (self.target_texture, self.target_depth, self.depth_texture) =
+            Renderer::create_targets(&self.device, size);
+        for shader in [&mut self.background_shader, &mut self.occlusion_shader]
+            .into_iter()
+            .flatten()
+        {
+            shader.resize(&self.device, size);
+        }
+        // Recreated at the new size on the next render that uses them
+        self.stereo_compositor = None;
+        self.compute_rasterizer = None;
+        self.depth_of_field_pass = None;
+        self.debug_view = None;
+    }
+
+    pub fn set_background(&mut self, background: &Background) {
+        self.downsampler
+            .set_background(&self.device, &self.queue, background);
+    }
+
+    pub fn supersampling(&self) -> u32 {
+        self.supersampling
+    }
+
+    // Clamped so the supersampled targets stay within the device's texture size limit
+    pub fn set_supersampling(&mut self, factor: u32) {
+        let (width, height) = self.size();
+        let max_factor = self.device.limits().max_texture_dimension_2d / width.max(height);
+        let factor = factor.clamp(1, max_factor.max(1));
+        if factor != self.supersampling {
+            self.supersampling = factor;
+            self.resize_internal();
+        }
+    }
+
+    // Time every render stage with GPU timestamps. Slows rendering down, since each frame waits for its
+    // timings to be read back
+    pub fn set_profiling(&mut self, enabled: bool) -> Result<(), Box<dyn std::error::Error>> {
+        if !enabled {
+            self.profiler = None;
+        } else if self.profiler.is_none() {
+            if !self.capabilities.timestamps {
+                return Err("This adapter doesn't support timestamp queries".into());
+            }
+            self.profiler = Some(Profiler::new(&self.device, &self.queue));
+        }
+        Ok(())
+    }
+
+    pub fn profiling(&self) -> bool {
+        self.profiler.is_some()
+    }
+
+    // Stage timings of the last frame rendered while profiling
+    pub fn frame_stats(&self) -> Option<&FrameStats> {
+        self.profiler.as_ref().map(Profiler::last_frame)
+    }
+
+    // Iterations, changed and empty pixels of each filling shader in the last frame, given the toggles it was
+    // rendered with. Waits for the frame to finish
+    pub fn filling_stats(
+        &self,
+        background_filling_toggle: bool,
+        occlusion_filling_toggle: bool,
+    ) -> Vec<FillingStats> {
+        [
+            (&self.background_shader, background_filling_toggle),
+            (&self.occlusion_shader, occlusion_filling_toggle),
+        ]
+        .into_iter()
+        .filter_map(|(shader, toggle)| shader.as_ref().filter(|_| toggle))
+        .map(|shader| shader.stats(&self.device))
+        .collect()
+    }
+
+    // Filling closes gaps one pixel per iteration, and at N times supersampling the gaps between points
+    // are N times wider, so the caps are given in output pixels and scaled up here
+    fn filling_iters(&self, iters: u32) -> u32 {
+        iters.saturating_mul(self.supersampling)
+    }
+
+    // Final count of each filling shader that ran in the last frame, background then occlusion
+    pub fn filling_counts(
+        &self,
+        background_filling_toggle: bool,
+        occlusion_filling_toggle: bool,
+    ) -> [Option<u32>; 2] {
+        [
+            (&self.background_shader, background_filling_toggle),
+            (&self.occlusion_shader, occlusion_filling_toggle),
+        ]
+        .map(|(shader, toggle)| {
+            shader
+                .as_ref()
+                .filter(|_| toggle)
+                .map(|shader| shader.count(&self.device))
+        })
+    }
+
+    fn internal_size(&self) -> (u32, u32) {
+        let (width, height) = self.size();
+        (width * self.supersampling, height * self.supersampling)
+    }
+
+    pub fn set_aspect_mode(&mut self, aspect_mode: AspectMode) {
+        self.aspect_mode = aspect_mode;
+        self.viewport_scale = aspect_mode.scale(self.input_size, self.size());
+        self.update_camera();
+    }
+
+    // The view as it's actually rendered, including the aspect handling
+    pub fn fit_to_output(&self, view_params: &ViewParams) -> ViewParams {
+        view_params.with_viewport(self.viewport_scale)
+    }
+
+    // Swap in a new image and depth map, keeping the device, pipelines and camera. Headless renders that
+    // follow the input's size are resized when the new input's dimensions differ, anything else keeps its
+    // targets and just refits the aspect
+    pub fn load_scene(
+        &mut self,
+        image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+        depth: &ImageBuffer<Luma<u8>, Vec<u8>>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if image.dimensions() != depth.dimensions() {
+            return Err(format!(
+                "Image is {:?} but the depth map is {:?}",
+                image.dimensions(),
+                depth.dimensions()
+            )
+            .into());
         }
+        let now = std::time::Instant::now();
+        self.layers[0].vertex_buffer =
+            Renderer::load_image(&self.device, image, depth, self.input_projection);
+        if image.dimensions() != self.input_size {
+            let follows_input = self.head_state.is_none() && self.size() == self.input_size;
+            self.input_size = image.dimensions();
+            if follows_input {
+                self.resize(self.input_size);
+            }
+            self.viewport_scale = self.aspect_mode.scale(self.input_size, self.size());
+        }
+        // The point count changes where the other layers' points start
+        self.update_camera();
+        eprintln!("Time to load scene: {:?}", std::time::Instant::now() - now);
+        Ok(())
     }
 
     fn load_image(
         device: &wgpu::Device,
         image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
         depth: &ImageBuffer<Luma<u8>, Vec<u8>>,
+        input_projection: InputProjection,
     ) -> wgpu::Buffer {
         let dims = image.dimensions();
         assert_eq!(image.dimensions(), depth.dimensions());
@@ -282,18 +618,25 @@ impl Renderer {
         // Generate vertices for each pixel. OpenGL coordinates have a minimum of -1 and maximum of 1
         for (y, (r1, r2)) in image.rows().zip(depth.rows()).enumerate() {
             for (x, (c1, c2)) in r1.zip(r2).enumerate() {
+                let normalized_depth =
+                    (c2.0[0] - min_depth) as f32 / (max_depth - min_depth as f32);
                 vertices.push(Vertex {
-                    position: [
-                        (x as f32 / dims.0 as f32) * 2.0 - 1.0,
-                        // Top of the screen is +1 in OpenGL
-                        (y as f32 / dims.1 as f32) * -2.0 + 1.0,
-                        ((c2.0[0] - min_depth) as f32 / (max_depth - min_depth as f32)) * -2.0
-                            + 0.9,
-                    ],
+                    position: match input_projection {
+                        InputProjection::Planar => [
+                            (x as f32 / dims.0 as f32) * 2.0 - 1.0,
+                            // Top of the screen is +1 in OpenGL
+                            (y as f32 / dims.1 as f32) * -2.0 + 1.0,
+                            normalized_depth * -2.0 + 0.9,
+                        ],
+                        InputProjection::Equirect => {
+                            equirect::unproject(x as u32, y as u32, dims, normalized_depth)
+                        }
+                    },
                     color: [
-                        c1.0[0] as f32 / 255.0,
-                        c1.0[1] as f32 / 255.0,
-                        c1.0[2] as f32 / 255.0,
+                        srgb_to_linear(c1.0[0] as f32 / 255.0),
+                        srgb_to_linear(c1.0[1] as f32 / 255.0),
+                        srgb_to_linear(c1.0[2] as f32 / 255.0),
+                        // Alpha isn't coverage here but a hole flag, 0 marks a pixel a point landed on
                         0.0,
                     ],
                 });
@@ -302,36 +645,80 @@ impl Renderer {
         let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Vertices"),
             contents: bytemuck::cast_slice(&vertices),
-            usage: wgpu::BufferUsages::VERTEX,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE,
         });
         vertex_buffer
     }
 
-    fn create_camera_buffer(device: &wgpu::Device) -> (ViewParams, wgpu::Buffer) {
-        let eye = Point3::new(0.0f32, 0.0, 1.0);
-        let look_at = Point3::new(0.0, 0.0, -0.1);
-        let view_params = ViewParams::new(
-            eye,
-            look_at,
-            Matrix4::new_orthographic(-1.0f32, 1.0, -1.0, 1.0, 0.0, 3.0),
-        );
-        let view_uniform = ViewUniform::from(view_params);
+    // Add another image and depth map as a layer with an identity model matrix. Returns its index
+    pub fn add_layer(
+        &mut self,
+        name: &str,
+        image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+        depth: &ImageBuffer<Luma<u8>, Vec<u8>>,
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        if image.dimensions() != depth.dimensions() {
+            return Err(format!(
+                "Image is {:?} but the depth map is {:?}",
+                image.dimensions(),
+                depth.dimensions()
+            )
+            .into());
+        }
+        let vertex_buffer = Renderer::load_image(&self.device, image, depth, self.input_projection);
+        self.layers.push(Layer::new(
+            &self.device,
+            &self.camera_bind_group_layout,
+            name,
+            vertex_buffer,
+        ));
+        self.update_camera();
+        Ok(self.layers.len() - 1)
+    }
 
-        let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Camera"),
-            contents: bytemuck::cast_slice(&[view_uniform]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
-        (view_params, camera_buffer)
+    pub fn remove_layer(&mut self, index: usize) -> Result<(), Box<dyn std::error::Error>> {
+        if index == 0 || index >= self.layers.len() {
+            return Err(format!("Can't remove layer {index}").into());
+        }
+        self.layers.remove(index);
+        self.update_camera();
+        Ok(())
+    }
+
+    fn initial_view(size: (u32, u32), input_projection: InputProjection) -> ViewParams {
+        match input_projection {
+            InputProjection::Planar => ViewParams::new(
+                Point3::new(0.0f32, 0.0, 1.0),
+                Point3::new(0.0, 0.0, -0.1),
+                Projection::Orthographic {
+                    left: -1.0,
+                    right: 1.0,
+                    bottom: -1.0,
+                    top: 1.0,
+                    near: 0.0,
+                    far: 3.0,
+                },
+            ),
+            // Start at the center of the panorama, where it looks undistorted
+            InputProjection::Equirect => ViewParams::new(
+                Point3::origin(),
+                Point3::new(0.0, 0.0, -1.0),
+                Projection::Perspective {
+                    fovy: std::f32::consts::FRAC_PI_2,
+                    aspect: size.0 as f32 / size.1 as f32,
+                    near: 0.01,
+                    far: equirect::MAX_RADIUS * 4.0,
+                },
+            ),
+        }
     }
 
-    // Create render pipeline including all bindgroups
+    // Create render pipeline and the layout of each layer's camera bindgroup
     fn create_pipeline(
         device: &wgpu::Device,
-        camera_buffer: &wgpu::Buffer,
         format: wgpu::TextureFormat,
         depth_format: wgpu::TextureFormat,
-    ) -> (wgpu::BindGroup, wgpu::RenderPipeline) {
+    ) -> (wgpu::BindGroupLayout, wgpu::RenderPipeline) {
         let raster_shader = device.create_shader_module(wgpu::include_wgsl!("shaders/raster.wgsl"));
         let camera_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
@@ -347,14 +734,6 @@ impl Renderer {
                     count: None,
                 }],
             });
-        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Camera Bindgroup"),
-            layout: &camera_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: camera_buffer.as_entire_binding(),
-            }],
-        });
         let render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("Render Pipeline Layout"),
@@ -366,11 +745,13 @@ impl Renderer {
             vertex: wgpu::VertexState {
                 module: &raster_shader,
                 entry_point: "vs_main",
+                compilation_options: Default::default(),
                 buffers: &[Vertex::desc()],
             },
             fragment: Some(wgpu::FragmentState {
                 module: &raster_shader,
                 entry_point: "fs_main",
+                compilation_options: Default::default(),
                 targets: &[
                     Some(wgpu::ColorTargetState {
                         format,
@@ -407,45 +788,72 @@ impl Renderer {
                 alpha_to_coverage_enabled: false,
             },
             multiview: None,
+            cache: None,
         });
-        (camera_bind_group, render_pipeline)
+        (camera_bind_group_layout, render_pipeline)
     }
 
     pub fn update_camera(&mut self) {
-        let view_uniform = ViewUniform::from(self.view_params);
-        self.queue.write_buffer(
-            &self.camera_buffer,
-            0,
-            bytemuck::cast_slice(&[view_uniform]),
-        );
+        self.write_camera(&self.view_params);
     }
 
-    pub fn render(
-        &mut self,
+    // Every layer gets the camera combined with its own model matrix
+    fn write_camera(&self, view_params: &ViewParams) {
+        let view_params = self.fit_to_output(view_params);
+        let view_projection = view_params.wgpu_projection() * view_params.camera;
+        let mut first_point = 0;
+        for layer in &self.layers {
+            layer.write(&self.queue, &view_projection, first_point);
+            first_point += layer.point_count();
+        }
+        if let (Some(settings), Some(pass)) = (&self.depth_of_field, &self.depth_of_field_pass) {
+            pass.write(
+                &self.queue,
+                &view_params,
+                settings,
+                self.supersampling as f32,
+            );
+        }
+        if let Some(debug_view) = &self.debug_view {
+            debug_view.write(&self.queue, &view_params);
+        }
+    }
+
+    // Rasterize the visible layers with whatever is in their camera buffers, run the filling shaders and
+    // blur for depth of field, or draw the debug view for the display mode. Returns the textures holding
+    // the final color and depth
+    fn encode_view(
+        &self,
+        command_encoder: &mut wgpu::CommandEncoder,
         background_filling_toggle: bool,
         occlusion_filling_toggle: bool,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let output = self
-            .head_state
-            .as_ref()
-            .map(|hs| hs.surface.get_current_texture())
-            .transpose()?;
+        mut profiler: Option<&mut Profiler>,
+    ) -> (&Texture, &Texture) {
+        if let Some(profiler) = profiler.as_deref_mut() {
+            profiler.mark(command_encoder);
+        }
         let view = &self.target_texture.texture_view;
         let depth_view = &self.target_depth.texture_view;
-        let mut command_encoder =
-            self.device
-                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                    label: Some("Point Cloud Render Encoder"),
-                });
-
+        if let (RasterMode::Compute, Some(compute_rasterizer)) =
+            (self.raster_mode, &self.compute_rasterizer)
         {
+            compute_rasterizer.run(
+                &self.device,
+                command_encoder,
+                &self.layers,
+                (&self.target_texture, &self.target_depth),
+            );
+        } else {
             let mut render_pass = command_encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Clear"),
                 color_attachments: &[
                     Some(wgpu::RenderPassColorAttachment {
-                        view: &view,
+                        view,
                         resolve_target: None,
                         ops: wgpu::Operations {
+                            // An alpha of 1 flags a hole: nothing was rasterized there. Filling keeps the
+                            // flag on pixels it can't reach and the downsampler turns it into the
+                            // background, so alpha only means coverage after downsampling
                             load: wgpu::LoadOp::Clear(wgpu::Color {
                                 r: 0.0,
                                 g: 0.0,
@@ -456,7 +864,7 @@ impl Renderer {
                         },
                     }),
                     Some(wgpu::RenderPassColorAttachment {
-                        view: &depth_view,
+                        view: depth_view,
                         resolve_target: None,
                         ops: wgpu::Operations {
                             load: wgpu::LoadOp::Clear(wgpu::Color {
@@ -481,89 +889,220 @@ impl Renderer {
                 occlusion_query_set: None,
             });
             render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
-            render_pass.draw(
-                0..(self.vertex_buffer.size() as usize / std::mem::size_of::<Vertex>()) as u32,
-                0..1,
-            );
+            for layer in self.layers.iter().filter(|layer| layer.visible) {
+                render_pass.set_vertex_buffer(0, layer.vertex_buffer.slice(..));
+                render_pass.set_bind_group(0, &layer.bind_group, &[]);
+                render_pass.draw(0..layer.point_count(), 0..1);
+            }
+        }
+        if let Some(profiler) = profiler.as_deref_mut() {
+            profiler.timestamp(command_encoder, "raster");
         }
 
-        let mut output_texture = &self.target_texture;
-        let mut output_depth = &self.target_depth;
+        let [raster, background, output] =
+            self.stage_outputs(background_filling_toggle, occlusion_filling_toggle);
 
         if let Some(background_shader) = &self.background_shader {
             if background_filling_toggle {
                 background_shader.run(
-                    &mut command_encoder,
-                    output_texture,
-                    output_depth,
-                    self.background_shading_iters,
+                    command_encoder,
+                    raster.0,
+                    raster.1,
+                    self.filling_iters(self.background_shading_iters),
+                    profiler.as_deref_mut(),
                 );
-                output_texture = &background_shader.textures[1].0;
-                output_depth = &background_shader.textures[1].1;
             }
         }
 
         if let Some(occlusion_shader) = &self.occlusion_shader {
             if occlusion_filling_toggle {
                 occlusion_shader.run(
-                    &mut command_encoder,
-                    &output_texture,
-                    &output_depth,
-                    self.occlusion_shading_iters,
+                    command_encoder,
+                    background.0,
+                    background.1,
+                    self.filling_iters(self.occlusion_shading_iters),
+                    profiler.as_deref_mut(),
                 );
-                output_texture = &occlusion_shader.textures[1].0;
-                output_depth = &occlusion_shader.textures[1].1;
             }
         }
 
-        let dst = if let Some(output) = &output {
-            &output.texture
-        } else {
-            &self.target_texture.texture
+        match (self.display_mode, &self.debug_view) {
+            (DisplayMode::Color, _) => {}
+            (DisplayMode::Raster, _) => return raster,
+            (DisplayMode::BackgroundFilling, _) => return background,
+            (DisplayMode::OcclusionFilling, _) => return output,
+            (mode, Some(debug_view)) => {
+                debug_view.run(
+                    &self.device,
+                    &self.queue,
+                    command_encoder,
+                    mode,
+                    output.1,
+                    raster.1,
+                );
+                return (&debug_view.texture, output.1);
+            }
+            (_, None) => {}
+        }
+
+        if let (Some(_), Some(pass)) = (&self.depth_of_field, &self.depth_of_field_pass) {
+            pass.run(&self.device, command_encoder, output);
+            if let Some(profiler) = profiler {
+                profiler.timestamp(command_encoder, "depth_of_field");
+            }
+            return (&pass.texture, output.1);
+        }
+        output
+    }
+
+    // Color and depth textures holding the result after rasterizing, after background filling and after
+    // occlusion filling. A stage that's off passes the previous one's through
+    fn stage_outputs(
+        &self,
+        background_filling_toggle: bool,
+        occlusion_filling_toggle: bool,
+    ) -> [(&Texture, &Texture); 3] {
+        let raster = (&self.target_texture, &self.target_depth);
+        let background = match &self.background_shader {
+            Some(shader) if background_filling_toggle => {
+                shader.output(self.filling_iters(self.background_shading_iters))
+            }
+            _ => raster,
         };
+        let occlusion = match &self.occlusion_shader {
+            Some(shader) if occlusion_filling_toggle => {
+                shader.output(self.filling_iters(self.occlusion_shading_iters))
+            }
+            _ => background,
+        };
+        [raster, background, occlusion]
+    }
 
-        // Copy final render result either to frontbuffer or in self.target_texture (for headless)
-        command_encoder.copy_texture_to_texture(
-            wgpu::ImageCopyTexture {
-                texture: &output_texture.texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            wgpu::ImageCopyTexture {
-                texture: dst,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            wgpu::Extent3d {
-                width: dst.width(),
-                height: dst.height(),
-                depth_or_array_layers: 1,
-            },
-        );
-        command_encoder.copy_texture_to_texture(
-            wgpu::ImageCopyTexture {
-                texture: &output_depth.texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            wgpu::ImageCopyTexture {
-                texture: &self.target_depth.texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            wgpu::Extent3d {
-                width: dst.width(),
-                height: dst.height(),
-                depth_or_array_layers: 1,
-            },
-        );
+    pub fn render(
+        &mut self,
+        background_filling_toggle: bool,
+        occlusion_filling_toggle: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.last_filling = (background_filling_toggle, occlusion_filling_toggle);
+        let output = self
+            .head_state
+            .as_ref()
+            .map(|hs| hs.surface.get_current_texture())
+            .transpose()?;
+        // Taken for the frame so encode_view can borrow it mutably alongside self
+        let mut profiler = self.profiler.take();
+
+        if self.stereo.is_some() && self.stereo_compositor.is_none() {
+            self.stereo_compositor =
+                Some(StereoCompositor::new(&self.device, self.internal_size()));
+        }
+        // Checked every frame, since loading a scene or adding a layer can push it over the limits
+        if self.raster_mode == RasterMode::Compute {
+            let limits = self.device.limits();
+            let int64_atomics = self.capabilities.int64_atomics;
+            if let Err(e) = ComputeRasterizer::check_limits(
+                &limits,
+                self.internal_size(),
+                &self.layers,
+                int64_atomics,
+            ) {
+                eprintln!("Falling back to the hardware rasterizer: {e}");
+                self.raster_mode = RasterMode::Hardware;
+            } else if self.compute_rasterizer.is_none() {
+                self.compute_rasterizer = Some(ComputeRasterizer::new(
+                    &self.device,
+                    self.internal_size(),
+                    int64_atomics,
+                ));
+            }
+        }
+        if self.display_mode.needs_debug_view() && self.debug_view.is_none() {
+            self.debug_view = Some(DebugView::new(&self.device, self.internal_size()));
+        }
+        if self.depth_of_field.is_some() && self.depth_of_field_pass.is_none() {
+            self.depth_of_field_pass =
+                Some(DepthOfFieldPass::new(&self.device, self.internal_size()));
+        }
+
+        let mut command_encoder =
+            self.device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Point Cloud Render Encoder"),
+                });
+
+        if let (Some(stereo), Some(compositor)) = (&self.stereo, &self.stereo_compositor) {
+            // Each eye is rasterized and filled on its own, so they're submitted one after the other
+            for (eye_texture, offset) in compositor.textures.iter().zip([-0.5, 0.5]) {
+                self.write_camera(
+                    &self
+                        .view_params
+                        .offset_eye(offset * stereo.interaxial, stereo.convergence),
+                );
+                let mut eye_encoder =
+                    self.device
+                        .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                            label: Some("Stereo Eye Encoder"),
+                        });
+                let (output_texture, _) = self.encode_view(
+                    &mut eye_encoder,
+                    background_filling_toggle,
+                    occlusion_filling_toggle,
+                    profiler.as_mut(),
+                );
+                output_texture.copy_to(&mut eye_encoder, &eye_texture.texture);
+                self.queue.submit(std::iter::once(eye_encoder.finish()));
+            }
+            self.write_camera(&self.view_params);
+            if let Some(profiler) = &mut profiler {
+                profiler.mark(&mut command_encoder);
+            }
+            compositor.run(
+                &self.device,
+                &mut command_encoder,
+                &self.target_texture,
+                stereo.mode,
+            );
+            if let Some(profiler) = &mut profiler {
+                profiler.timestamp(&mut command_encoder, "stereo_compose");
+            }
+            self.downsampler.run(
+                &self.device,
+                &mut command_encoder,
+                &self.target_texture,
+                &self.target_depth,
+            );
+        } else {
+            // Settings like the focus can change without a camera update
+            self.write_camera(&self.view_params);
+            let (output_texture, output_depth) = self.encode_view(
+                &mut command_encoder,
+                background_filling_toggle,
+                occlusion_filling_toggle,
+                profiler.as_mut(),
+            );
+            self.downsampler.run(
+                &self.device,
+                &mut command_encoder,
+                output_texture,
+                output_depth,
+            );
+        }
+
+        if let Some(profiler) = &mut profiler {
+            profiler.timestamp(&mut command_encoder, "downsample");
+            profiler.resolve(&mut command_encoder);
+        }
+
+        if let Some(output) = &output {
+            self.downsampler
+                .texture
+                .copy_to(&mut command_encoder, &output.texture);
+        }
         self.queue.submit(std::iter::once(command_encoder.finish()));
+        if let Some(profiler) = &mut profiler {
+            profiler.finish_frame(&self.device);
+        }
+        self.profiler = profiler;
 
         if let Some(output) = output {
             output.present();
@@ -572,7 +1111,7 @@ impl Renderer {
     }
 
     fn read_texture(&self, texture: &wgpu::Texture) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-        let bpp = texture.format().block_size(None).unwrap();
+        let bpp = texture.format().block_copy_size(None).unwrap();
         dbg!(bpp);
         // let bpp = match texture.format() {
         //     wgpu::TextureFormat::
@@ -610,7 +1149,7 @@ impl Renderer {
                 buffer: &buffer,
                 layout: wgpu::ImageDataLayout {
                     offset: 0,
-                    bytes_per_row: Some((padded_row_bytes as u32).try_into()?),
+                    bytes_per_row: Some(padded_row_bytes.try_into()?),
                     rows_per_image: None,
                 },
             },
@@ -635,49 +1174,290 @@ impl Renderer {
     pub fn read_front_buffer(
         &self,
     ) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, Box<dyn std::error::Error>> {
-        let mut buf = self.read_texture(&self.target_texture.texture)?;
+        let texture = &self.downsampler.texture.texture;
+        let mut buf = self.read_texture(texture)?;
         // Convert bgra to rgba
-        if let wgpu::TextureFormat::Bgra8Unorm = self.target_texture.texture.format() {
+        if let wgpu::TextureFormat::Bgra8Unorm = texture.format() {
             for chunk in buf.chunks_mut(4) {
                 chunk.swap(0, 2);
             }
         }
-        let image: ImageBuffer<Rgba<u8>, Vec<u8>> = ImageBuffer::from_vec(
-            self.target_texture.texture.width(),
-            self.target_texture.texture.height(),
-            buf,
-        )
-        .unwrap();
+        let image: ImageBuffer<Rgba<u8>, Vec<u8>> =
+            ImageBuffer::from_vec(texture.width(), texture.height(), buf).unwrap();
         Ok(image)
     }
 
     pub fn read_depth(
         &self,
     ) -> Result<ImageBuffer<Luma<u16>, Vec<u16>>, Box<dyn std::error::Error>> {
+        // The depth target only holds the last eye rendered, before filling
+        if self.stereo.is_some() {
+            return Err("Depth isn't available in stereo mode".into());
+        }
+        let depth = &self.downsampler.depth.texture;
         let buf = unsafe {
-            let read_texture = &self.read_texture(&self.target_depth.texture)?;
+            let read_texture = &self.read_texture(depth)?;
             let (l, buf, r) = read_texture.align_to::<f32>();
             assert!(l.is_empty() && r.is_empty());
             buf.to_owned()
         };
         let reduced_buf = buf.iter().map(|f| (f * 65535.0) as u16).collect();
-        dbg!(
-            buf.len(),
-            self.target_depth.texture.width() * self.target_depth.texture.height()
+        debug_assert_eq!(buf.len(), (depth.width() * depth.height()) as usize);
+        let image: ImageBuffer<Luma<u16>, Vec<u16>> =
+            ImageBuffer::from_vec(depth.width(), depth.height(), reduced_buf).unwrap();
+        Ok(image)
+    }
+    // Move the focus plane to whatever is at pixel in the last frame (output coordinates). Returns the new
+    // focus depth, or None if nothing was rendered there
+    pub fn focus_at(
+        &mut self,
+        (x, y): (u32, u32),
+    ) -> Result<Option<f32>, Box<dyn std::error::Error>> {
+        if self.stereo.is_some() {
+            return Err("Depth isn't available in stereo mode".into());
+        }
+        let depth = &self.downsampler.depth.texture;
+        let (width, height) = (depth.width(), depth.height());
+        if x >= width || y >= height {
+            return Err(format!("{x}, {y} is outside the {width}x{height} output").into());
+        }
+        let buf = self.read_texture(depth)?;
+        let offset = (y * width + x) as usize * 4;
+        let z = f32::from_ne_bytes(buf[offset..offset + 4].try_into()?);
+        if z == 0.0 {
+            return Ok(None);
+        }
+        let inverse_projection = self
+            .fit_to_output(&self.view_params)
+            .wgpu_projection()
+            .try_inverse()
+            .ok_or("The projection isn't invertible")?;
+        let view = inverse_projection
+            * Vector4::new(
+                (x as f32 + 0.5) / width as f32 * 2.0 - 1.0,
+                1.0 - (y as f32 + 0.5) / height as f32 * 2.0,
+                z,
+                1.0,
+            );
+        let focus = -view.z / view.w;
+        self.view_params.set_focus(Some(focus));
+        Ok(Some(focus))
+    }
+
+    // Where each pixel of the last frame came from, see ProvenanceMask for the values
+    pub fn read_mask(
+        &mut self,
+    ) -> Result<ImageBuffer<Luma<u8>, Vec<u8>>, Box<dyn std::error::Error>> {
+        // The filling textures only hold the last eye rendered
+        if self.stereo.is_some() {
+            return Err("The provenance mask isn't available in stereo mode".into());
+        }
+        if self.provenance_mask.is_none() {
+            self.provenance_mask = Some(ProvenanceMask::new(&self.device, self.size()));
+        }
+        let provenance_mask = self.provenance_mask.as_ref().unwrap();
+        let [raster, background, output] =
+            self.stage_outputs(self.last_filling.0, self.last_filling.1);
+        let mut command_encoder =
+            self.device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Provenance Encoder"),
+                });
+        provenance_mask.run(
+            &self.device,
+            &mut command_encoder,
+            raster.1,
+            background.1,
+            output,
         );
-        let image: ImageBuffer<Luma<u16>, Vec<u16>> = ImageBuffer::from_vec(
-            self.target_depth.texture.width(),
-            self.target_depth.texture.height(),
-            reduced_buf,
+        self.queue.submit(std::iter::once(command_encoder.finish()));
+
+        let texture = &provenance_mask.texture.texture;
+        let buf = self.read_texture(texture)?;
+        let image: ImageBuffer<Luma<u8>, Vec<u8>> = ImageBuffer::from_vec(
+            texture.width(),
+            texture.height(),
+            buf.chunks(4).map(|pixel| pixel[0]).collect(),
         )
         .unwrap();
         Ok(image)
     }
-    pub fn save_screenshot(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let image = self.read_front_buffer()?;
-        let depth = self.read_depth()?;
-        image.save(path)?;
-        depth.save("depthscreenshot.png")?;
+
+    pub fn size(&self) -> (u32, u32) {
+        (
+            self.downsampler.texture.texture.width(),
+            self.downsampler.texture.texture.height(),
+        )
+    }
+
+    // Render each view in frames to {output_dir}/frame-NNNN.png, restoring the current view afterwards.
+    // Camera poses are written to {output_dir}/transforms.json
+    pub fn render_frames(
+        &mut self,
+        frames: &[ViewParams],
+        output_dir: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let frames: Vec<Pose> = frames
+            .iter()
+            .enumerate()
+            .map(|(i, frame)| Pose {
+                name: format!("frame-{i:04}"),
+                view_params: *frame,
+                size: None,
+            })
+            .collect();
+        self.render_named_frames(&frames, output_dir)
+    }
+
+    // Same as render_frames, but each view is saved as {output_dir}/{name}.png. Poses with a size are
+    // rendered at exactly that size, since their projection already covers the whole image
+    pub fn render_named_frames(
+        &mut self,
+        frames: &[Pose],
+        output_dir: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        std::fs::create_dir_all(output_dir)?;
+        let mut transforms = TransformsFile::new(&format!("{output_dir}/transforms.json"));
+        let view_params = self.view_params;
+        let size = self.size();
+        let supersampling = self.supersampling;
+        let viewport_scale = self.viewport_scale;
+        // The view and size are restored whether or not every frame rendered
+        let mut render = || -> Result<(), Box<dyn std::error::Error>> {
+            for frame in frames {
+                let path = format!("{output_dir}/{}.png", frame.name);
+                if let Some(frame_size) = frame.size {
+                    self.set_size(frame_size)?;
+                    self.viewport_scale = [1.0, 1.0];
+                }
+                self.view_params = frame.view_params;
+                self.update_camera();
+                self.render(true, true)?;
+                self.read_front_buffer()?.save(&path)?;
+                transforms.add_frame(&path, &self.fit_to_output(&self.view_params), self.size())?;
+            }
+            Ok(())
+        };
+        let result = render();
+        self.resize(size);
+        self.set_supersampling(supersampling);
+        self.viewport_scale = viewport_scale;
+        self.view_params = view_params;
+        self.update_camera();
+        result
+    }
+
+    // Render every view of a quilt with a horizontally shifted camera and tile them together
+    pub fn render_quilt(
+        &mut self,
+        quilt: &Quilt,
+    ) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, Box<dyn std::error::Error>> {
+        let mut output = ImageBuffer::new(quilt.width, quilt.height);
+        let view_params = self.view_params;
+        let stereo = self.stereo.take();
+        let convergence = (view_params.look_at() - view_params.eye()).magnitude();
+        // Stereo and the view are restored whether or not every view rendered
+        let mut render = || -> Result<(), Box<dyn std::error::Error>> {
+            for (i, offset) in quilt.offsets(convergence).into_iter().enumerate() {
+                self.view_params = view_params.offset_eye(offset, convergence);
+                self.update_camera();
+                self.render(true, true)?;
+                quilt.place(&mut output, i, &self.read_front_buffer()?);
+            }
+            Ok(())
+        };
+        let result = render();
+        self.stereo = stereo;
+        self.view_params = view_params;
+        self.update_camera();
+        result.map(|_| output)
+    }
+
+    // Render each eye as square cube faces around the current camera and resample them into equirect images
+    pub fn render_vr(
+        &mut self,
+        vr: &Vr,
+    ) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, Box<dyn std::error::Error>> {
+        const NEAR: f32 = 0.01;
+        const FAR: f32 = 10.0;
+        let view_params = self.view_params;
+        let size = self.size();
+        let supersampling = self.supersampling;
+        let viewport_scale = self.viewport_scale;
+        let stereo = self.stereo.take();
+        // Faces are rendered off screen, so the window surface keeps its size and isn't presented to
+        let head_state = self.head_state.take();
+        // Off-axis projection would replace each face's frustum, so the faces turn around the plain camera
+        let mut base = view_params;
+        base.set_screen(None);
+        let rotations = vr::face_rotations(vr.layout);
+        // Everything is restored whether or not every face rendered
+        let mut render = || -> Result<_, Box<dyn std::error::Error>> {
+            self.set_size((vr.face_size(), vr.face_size()))?;
+            // Cube faces have to cover the whole target
+            self.viewport_scale = [1.0, 1.0];
+            let mut eyes = Vec::with_capacity(2);
+            for offset in [-0.5, 0.5] {
+                let mut faces = Vec::with_capacity(rotations.len());
+                for rotation in &rotations {
+                    // The eyes are offset along each face's own x axis, so every direction gets some parallax
+                    let translation =
+                        Matrix4::new_translation(&Vector3::new(-offset * vr.interaxial, 0.0, 0.0));
+                    self.view_params = base;
+                    self.view_params.camera = translation * rotation * base.camera;
+                    self.view_params.set_projection(Projection::Frustum {
+                        left: -NEAR,
+                        right: NEAR,
+                        bottom: -NEAR,
+                        top: NEAR,
+                        near: NEAR,
+                        far: FAR,
+                    });
+                    self.update_camera();
+                    self.render(true, true)?;
+                    faces.push(self.read_front_buffer()?);
+                }
+                eyes.push(vr.resample(&rotations, &faces));
+            }
+            Ok(vr.compose(&eyes[0], &eyes[1]))
+        };
+        let result = render();
+        self.resize(size);
+        self.set_supersampling(supersampling);
+        self.viewport_scale = viewport_scale;
+        self.head_state = head_state;
+        self.stereo = stereo;
+        self.view_params = view_params;
+        self.update_camera();
+        result
+    }
+
+    // Optionally record the camera for this screenshot in a transforms.json sidecar
+    pub fn save_screenshot(
+        &mut self,
+        path: &str,
+        transforms: Option<&mut TransformsFile>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        // A panorama has no single depth map or pinhole camera, so only the image is saved
+        if let Some(vr) = self.vr {
+            self.render_vr(&vr)?.save(path)?;
+            return Ok(());
+        }
+        self.read_front_buffer()?.save(path)?;
+        // Neither depth nor the mask exists for the composed stereo frame, only for one eye. Both go next to
+        // the screenshot as <name>-depth.png and <name>-mask.png
+        if self.stereo.is_none() {
+            let path = std::path::Path::new(path);
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            let sibling = |suffix| path.with_file_name(format!("{stem}-{suffix}.png"));
+            self.read_depth()?.save(sibling("depth"))?;
+            if self.save_mask {
+                self.read_mask()?.save(sibling("mask"))?;
+            }
+        }
+        if let Some(transforms) = transforms {
+            transforms.add_frame(path, &self.fit_to_output(&self.view_params), self.size())?;
+        }
         Ok(())
     }
 }
@@ -0,0 +1,109 @@
+use image::{io::Reader as ImageReader, ImageBuffer, Luma, Rgba};
+
+pub type ImagePair = (
+    ImageBuffer<Rgba<u8>, Vec<u8>>,
+    ImageBuffer<Luma<u8>, Vec<u8>>,
+);
+
+// An image and depth map pair that can be loaded into a running renderer
+#[derive(Clone, Debug)]
+pub struct Scene {
+    pub image_path: String,
+    pub depth_path: String,
+}
+
+impl Scene {
+    pub fn load(&self) -> Result<ImagePair, Box<dyn std::error::Error>> {
+        let image = ImageReader::open(&self.image_path)?.decode()?.to_rgba8();
+        let depth = ImageReader::open(&self.depth_path)?.decode()?.to_luma8();
+        Ok((image, depth))
+    }
+
+    // File stem of the image, used to keep batch outputs of different scenes apart
+    pub fn name(&self) -> String {
+        std::path::Path::new(&self.image_path)
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+// A scene list has an image path and a depth path per line, separated by whitespace. Blank lines and lines
+// starting with # are skipped
+pub fn load_scene_list(path: &str) -> Result<Vec<Scene>, Box<dyn std::error::Error>> {
+    let mut scenes = Vec::new();
+    for (number, line) in std::fs::read_to_string(path)?.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut paths = line.split_whitespace();
+        match (paths.next(), paths.next(), paths.next()) {
+            (Some(image_path), Some(depth_path), None) => scenes.push(Scene {
+                image_path: image_path.to_string(),
+                depth_path: depth_path.to_string(),
+            }),
+            _ => {
+                return Err(format!(
+                    "{path}:{}: expected an image path and a depth path",
+                    number + 1
+                )
+                .into())
+            }
+        }
+    }
+    Ok(scenes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scene_list(test: &str, contents: &str) -> String {
+        let path = std::env::temp_dir().join(format!(
+            "pointcloudrender-{test}-{}.txt",
+            std::process::id()
+        ));
+        std::fs::write(&path, contents).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn skips_comments_and_blank_lines() {
+        let path = scene_list(
+            "scenes",
+            "# image depth\n\nphotos/a.jpg depth/a.png\n  photos/b.v2.jpg\tdepth/b.png  \n",
+        );
+        let scenes = load_scene_list(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        let paths: Vec<(&str, &str)> = scenes
+            .iter()
+            .map(|scene| (scene.image_path.as_str(), scene.depth_path.as_str()))
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                ("photos/a.jpg", "depth/a.png"),
+                ("photos/b.v2.jpg", "depth/b.png")
+            ]
+        );
+        assert_eq!(scenes[1].name(), "b.v2");
+    }
+
+    #[test]
+    fn reports_the_bad_line() {
+        for (test, contents) in [
+            ("scenes-one-path", "a.jpg a.png\n\nb.jpg\n"),
+            ("scenes-three-paths", "a.jpg a.png\n\nb.jpg b.png c.png\n"),
+        ] {
+            let path = scene_list(test, contents);
+            let error = load_scene_list(&path).unwrap_err().to_string();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(
+                error,
+                format!("{path}:3: expected an image path and a depth path")
+            );
+        }
+    }
+}
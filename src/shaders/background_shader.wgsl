@@ -1,17 +1,53 @@
 @group(0) @binding(0)
-var input_image: texture_storage_2d<bgra8unorm, read>;
+var input_image: texture_2d<f32>;
 @group(0) @binding(1)
-var output_image: texture_storage_2d<bgra8unorm, write>;
+var output_image: texture_storage_2d<rgba16float, write>;
 @group(0) @binding(3)
-var input_depth: texture_storage_2d<r32float, read>;
+var input_depth: texture_2d<f32>;
 @group(0) @binding(4)
 var output_depth: texture_storage_2d<r32float, write>;
 
+struct Convergence {
+   // Pixels this iteration changed
+   changed: atomic<u32>,
+   // Pixels this iteration left empty, which filling may never reach (letterboxing, outside the cloud)
+   empty: atomic<u32>,
+}
+@group(0) @binding(2)
+var<storage, read_write> convergence: Convergence;
+// Summed per workgroup first so every pixel isn't contending for the same two counters
+var<workgroup> workgroup_changed: atomic<u32>;
+var<workgroup> workgroup_empty: atomic<u32>;
+
+// Write a pixel's output and count it towards convergence
+fn store(pixel: vec2<u32>, input: vec4<f32>, depth_in: f32, color: vec4<f32>, depth: f32) {
+   textureStore(output_image, pixel, color);
+   textureStore(output_depth, pixel, vec4(depth));
+   let size: vec2<u32> = textureDimensions(input_image);
+   if pixel.x >= size.x || pixel.y >= size.y {
+      return;
+   }
+   if any(color != input) || depth != depth_in {
+      atomicAdd(&workgroup_changed, 1u);
+   }
+   if depth == 0.0 {
+      atomicAdd(&workgroup_empty, 1u);
+   }
+}
+
+fn flush_counts(local_index: u32) {
+   workgroupBarrier();
+   if local_index == 0u {
+      atomicAdd(&convergence.changed, atomicLoad(&workgroup_changed));
+      atomicAdd(&convergence.empty, atomicLoad(&workgroup_empty));
+   }
+}
+
 
 // Return a clamped read into the texture so we don't go out of bounds
 fn c_load(coords: vec2<i32>, dimensions: vec2<u32>) -> f32 {
    let clamped: vec2<i32> = vec2<i32>(clamp(coords.x, 0, i32(dimensions.x)), clamp(coords.y, 0, i32(dimensions.y)));
-   return textureLoad(input_depth, clamped).r;
+   return textureLoad(input_depth, clamped, 0).r;
 }
 
 // apparently you can't index arrays by variable
@@ -23,10 +59,10 @@ fn apply_kernel(kernel: array<f32, 9>, neighbors: array<f32, 9>) -> f32 {
 //TODO: set to 8x8
 @compute
 @workgroup_size(8, 8, 1)
-fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+fn main(@builtin(global_invocation_id) global_id: vec3<u32>, @builtin(local_invocation_index) local_index: u32) {
    let size: vec2<u32> = textureDimensions(input_image);
 
-   let load: vec4<f32> = textureLoad(input_image, global_id.xy);
+   let load: vec4<f32> = textureLoad(input_image, global_id.xy, 0);
    let id: vec2<i32> = vec2<i32>(i32(global_id.x), i32(global_id.y));
    var offsets = array<vec2<i32>, 9>(
       vec2(-1, -1), vec2(0, -1), vec2(1, -1),
@@ -40,8 +76,7 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
    );
 
    if (abs(neighbors[4]) > 1e-9) {
-      textureStore(output_image, global_id.xy, load);
-      textureStore(output_depth, global_id.xy, vec4(neighbors[4]));
+      store(global_id.xy, load, neighbors[4], load, neighbors[4]);
    }
    else {
       let kernel1 = array<f32, 9>(
@@ -113,7 +148,8 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
 
 
 
-      textureStore(output_image, global_id.xy, textureLoad(input_image, id + offsets[min_idx]));
-      textureStore(output_depth, global_id.xy, vec4(neighbors[min_idx]));
+      // The neighbor's alpha comes along, clearing the hole flag (alpha 1) this pixel was cleared to
+      store(global_id.xy, load, neighbors[4], textureLoad(input_image, id + offsets[min_idx], 0), neighbors[min_idx]);
    }
+   flush_counts(local_index);
 }
@@ -0,0 +1,131 @@
+struct Layer {
+   viewprojection: mat4x4<f32>,
+   // Index of this layer's first point when every layer's points are numbered in order
+   first_point: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> layer: Layer;
+// Vertex buffer read as raw floats: position xyz then color rgba
+@group(0) @binding(1)
+var<storage, read> vertices: array<f32>;
+@group(0) @binding(2)
+var<storage, read_write> depth_buffer: array<atomic<u32>>;
+@group(0) @binding(3)
+var<storage, read_write> index_buffer: array<atomic<u32>>;
+@group(0) @binding(4)
+var output_image: texture_storage_2d<rgba16float, write>;
+@group(0) @binding(5)
+var output_depth: texture_storage_2d<r32float, write>;
+
+const VERTEX_STRIDE: u32 = 7u;
+const POINT_WORKGROUP_SIZE: u32 = 256u;
+const EMPTY: u32 = 0xffffffffu;
+
+struct Projected {
+   pixel: u32,
+   depth: u32,
+   visible: bool,
+}
+
+// Point dispatches are split over y when there are more workgroups than fit in x
+fn point_index(global_id: vec3<u32>, num_workgroups: vec3<u32>) -> u32 {
+   return global_id.y * num_workgroups.x * POINT_WORKGROUP_SIZE + global_id.x;
+}
+
+fn point_count() -> u32 {
+   return arrayLength(&vertices) / VERTEX_STRIDE;
+}
+
+fn project(index: u32) -> Projected {
+   var out: Projected;
+   out.visible = false;
+   if index >= point_count() {
+      return out;
+   }
+   let size: vec2<u32> = textureDimensions(output_image);
+   let base = index * VERTEX_STRIDE;
+   let clip = layer.viewprojection * vec4(vertices[base], vertices[base + 1u], vertices[base + 2u], 1.0);
+   if clip.w <= 0.0 {
+      return out;
+   }
+   let ndc = clip.xyz / clip.w;
+   if abs(ndc.x) >= 1.0 || abs(ndc.y) >= 1.0 || ndc.z < 0.0 || ndc.z >= 1.0 {
+      return out;
+   }
+   let x = min(u32((ndc.x + 1.0) * 0.5 * f32(size.x)), size.x - 1u);
+   let y = min(u32((1.0 - ndc.y) * 0.5 * f32(size.y)), size.y - 1u);
+   out.pixel = y * size.x + x;
+   // Non-negative floats sort the same way as their bit patterns, so atomicMin on the bits is a depth test
+   out.depth = bitcast<u32>(ndc.z);
+   out.visible = true;
+   return out;
+}
+
+@compute
+@workgroup_size(8, 8, 1)
+fn clear(@builtin(global_invocation_id) global_id: vec3<u32>) {
+   let size: vec2<u32> = textureDimensions(output_image);
+   if global_id.x >= size.x || global_id.y >= size.y {
+      return;
+   }
+   let pixel = global_id.y * size.x + global_id.x;
+   atomicStore(&depth_buffer[pixel], EMPTY);
+   atomicStore(&index_buffer[pixel], EMPTY);
+}
+
+// First pass: find the nearest depth at every pixel
+@compute
+@workgroup_size(256, 1, 1)
+fn depth(@builtin(global_invocation_id) global_id: vec3<u32>, @builtin(num_workgroups) num_workgroups: vec3<u32>) {
+   let projected = project(point_index(global_id, num_workgroups));
+   if projected.visible {
+      atomicMin(&depth_buffer[projected.pixel], projected.depth);
+   }
+}
+
+// Second pass: of the points at that depth, keep the lowest index so the result is deterministic. Indices
+// count through the layers, so earlier layers win ties like they do in the hardware raster pass
+@compute
+@workgroup_size(256, 1, 1)
+fn index(@builtin(global_invocation_id) global_id: vec3<u32>, @builtin(num_workgroups) num_workgroups: vec3<u32>) {
+   let point = point_index(global_id, num_workgroups);
+   let projected = project(point);
+   if projected.visible && atomicLoad(&depth_buffer[projected.pixel]) == projected.depth {
+      atomicMin(&index_buffer[projected.pixel], layer.first_point + point);
+   }
+}
+
+// Give pixels no point landed on the same empty values the hardware raster pass clears to
+@compute
+@workgroup_size(8, 8, 1)
+fn resolve_empty(@builtin(global_invocation_id) global_id: vec3<u32>) {
+   let size: vec2<u32> = textureDimensions(output_image);
+   if global_id.x >= size.x || global_id.y >= size.y {
+      return;
+   }
+   if atomicLoad(&index_buffer[global_id.y * size.x + global_id.x]) == EMPTY {
+      // Alpha 1 is the hole flag, see the clear in Renderer::encode_view
+      textureStore(output_image, global_id.xy, vec4(0.0, 0.0, 0.0, 1.0));
+      textureStore(output_depth, global_id.xy, vec4(0.0));
+   }
+}
+
+// Write the color and depth of pixels won by one of this layer's points
+@compute
+@workgroup_size(8, 8, 1)
+fn resolve(@builtin(global_invocation_id) global_id: vec3<u32>) {
+   let size: vec2<u32> = textureDimensions(output_image);
+   if global_id.x >= size.x || global_id.y >= size.y {
+      return;
+   }
+   let pixel = global_id.y * size.x + global_id.x;
+   let winner = atomicLoad(&index_buffer[pixel]);
+   if winner == EMPTY || winner < layer.first_point || winner - layer.first_point >= point_count() {
+      return;
+   }
+   let base = (winner - layer.first_point) * VERTEX_STRIDE;
+   let color = vec4(vertices[base + 3u], vertices[base + 4u], vertices[base + 5u], vertices[base + 6u]);
+   textureStore(output_image, global_id.xy, color);
+   textureStore(output_depth, global_id.xy, vec4(bitcast<f32>(atomicLoad(&depth_buffer[pixel]))));
+}
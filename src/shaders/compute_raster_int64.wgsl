@@ -0,0 +1,128 @@
+// Same passes as compute_raster.wgsl, with visibility resolved in one pass over 64-bit keys. Needs
+// SHADER_INT64 and SHADER_INT64_ATOMIC_MIN_MAX
+struct Layer {
+   viewprojection: mat4x4<f32>,
+   // Index of this layer's first point when every layer's points are numbered in order
+   first_point: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> layer: Layer;
+// Vertex buffer read as raw floats: position xyz then color rgba
+@group(0) @binding(1)
+var<storage, read> vertices: array<f32>;
+// The key buffer, bound as atomics for the splat pass only. Metal can't load or store 64-bit atomics, so
+// the other passes bind the same buffer as plain u64s in a separate bind group
+@group(0) @binding(2)
+var<storage, read_write> splat_keys: array<atomic<u64>>;
+@group(0) @binding(3)
+var<storage, read_write> keys: array<u64>;
+@group(0) @binding(4)
+var output_image: texture_storage_2d<rgba16float, write>;
+@group(0) @binding(5)
+var output_depth: texture_storage_2d<r32float, write>;
+
+const VERTEX_STRIDE: u32 = 7u;
+const POINT_WORKGROUP_SIZE: u32 = 256u;
+// All ones, which sorts after every real key since their depth half is below the bits of 1.0
+const EMPTY: u64 = 18446744073709551615lu;
+
+struct Projected {
+   pixel: u32,
+   depth: u32,
+   visible: bool,
+}
+
+// Point dispatches are split over y when there are more workgroups than fit in x
+fn point_index(global_id: vec3<u32>, num_workgroups: vec3<u32>) -> u32 {
+   return global_id.y * num_workgroups.x * POINT_WORKGROUP_SIZE + global_id.x;
+}
+
+fn point_count() -> u32 {
+   return arrayLength(&vertices) / VERTEX_STRIDE;
+}
+
+fn project(index: u32) -> Projected {
+   var out: Projected;
+   out.visible = false;
+   if index >= point_count() {
+      return out;
+   }
+   let size: vec2<u32> = textureDimensions(output_image);
+   let base = index * VERTEX_STRIDE;
+   let clip = layer.viewprojection * vec4(vertices[base], vertices[base + 1u], vertices[base + 2u], 1.0);
+   if clip.w <= 0.0 {
+      return out;
+   }
+   let ndc = clip.xyz / clip.w;
+   if abs(ndc.x) >= 1.0 || abs(ndc.y) >= 1.0 || ndc.z < 0.0 || ndc.z >= 1.0 {
+      return out;
+   }
+   let x = min(u32((ndc.x + 1.0) * 0.5 * f32(size.x)), size.x - 1u);
+   let y = min(u32((1.0 - ndc.y) * 0.5 * f32(size.y)), size.y - 1u);
+   out.pixel = y * size.x + x;
+   // Non-negative floats sort the same way as their bit patterns
+   out.depth = bitcast<u32>(ndc.z);
+   out.visible = true;
+   return out;
+}
+
+@compute
+@workgroup_size(8, 8, 1)
+fn clear(@builtin(global_invocation_id) global_id: vec3<u32>) {
+   let size: vec2<u32> = textureDimensions(output_image);
+   if global_id.x >= size.x || global_id.y >= size.y {
+      return;
+   }
+   keys[global_id.y * size.x + global_id.x] = EMPTY;
+}
+
+// Depth in the high half and point index in the low half, so the smallest key is the nearest point, and
+// of equally near points the one with the lowest index. Indices count through the layers, so earlier
+// layers win ties like they do in the hardware raster pass
+@compute
+@workgroup_size(256, 1, 1)
+fn splat(@builtin(global_invocation_id) global_id: vec3<u32>, @builtin(num_workgroups) num_workgroups: vec3<u32>) {
+   let point = point_index(global_id, num_workgroups);
+   let projected = project(point);
+   if projected.visible {
+      atomicMin(&splat_keys[projected.pixel], (u64(projected.depth) << 32u) | u64(layer.first_point + point));
+   }
+}
+
+// Give pixels no point landed on the same empty values the hardware raster pass clears to
+@compute
+@workgroup_size(8, 8, 1)
+fn resolve_empty(@builtin(global_invocation_id) global_id: vec3<u32>) {
+   let size: vec2<u32> = textureDimensions(output_image);
+   if global_id.x >= size.x || global_id.y >= size.y {
+      return;
+   }
+   if keys[global_id.y * size.x + global_id.x] == EMPTY {
+      // Alpha 1 is the hole flag, see the clear in Renderer::encode_view
+      textureStore(output_image, global_id.xy, vec4(0.0, 0.0, 0.0, 1.0));
+      textureStore(output_depth, global_id.xy, vec4(0.0));
+   }
+}
+
+// Write the color and depth of pixels won by one of this layer's points
+@compute
+@workgroup_size(8, 8, 1)
+fn resolve(@builtin(global_invocation_id) global_id: vec3<u32>) {
+   let size: vec2<u32> = textureDimensions(output_image);
+   if global_id.x >= size.x || global_id.y >= size.y {
+      return;
+   }
+   let key = keys[global_id.y * size.x + global_id.x];
+   if key == EMPTY {
+      return;
+   }
+   let winner = u32(key & u64(0xffffffffu));
+   if winner < layer.first_point || winner - layer.first_point >= point_count() {
+      return;
+   }
+   let base = (winner - layer.first_point) * VERTEX_STRIDE;
+   let color = vec4(vertices[base + 3u], vertices[base + 4u], vertices[base + 5u], vertices[base + 6u]);
+   textureStore(output_image, global_id.xy, color);
+   textureStore(output_depth, global_id.xy, vec4(bitcast<f32>(u32(key >> 32u))));
+}
@@ -0,0 +1,191 @@
+struct Params {
+   // Unprojects the depth target back into view space
+   inverse_projection: mat4x4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> params: Params;
+// Depth of the stage being shown
+@group(0) @binding(1)
+var input_depth: texture_2d<f32>;
+// Depth straight out of the rasterizer, before any filling
+@group(0) @binding(2)
+var raster_depth: texture_2d<f32>;
+// Nearest and furthest view depth in the frame, as float bits
+@group(0) @binding(3)
+var<storage, read_write> depth_range: array<atomic<u32>, 2>;
+@group(0) @binding(4)
+var output_image: texture_storage_2d<rgba16float, write>;
+
+// Colormaps and normals are display values, but the output is linear and gets sRGB encoded on the way out
+fn srgb_to_linear(color: vec3<f32>) -> vec3<f32> {
+   let low = color / 12.92;
+   let high = pow((color + 0.055) / 1.055, vec3(2.4));
+   return select(high, low, color <= vec3(0.04045));
+}
+
+// Alpha 0 keeps the background from being composited over the debug output
+fn store(pixel: vec2<u32>, color: vec3<f32>) {
+   textureStore(output_image, pixel, vec4(srgb_to_linear(clamp(color, vec3(0.0), vec3(1.0))), 0.0));
+}
+
+fn view_position(pixel: vec2<i32>, depth: f32) -> vec3<f32> {
+   let size = vec2<f32>(textureDimensions(input_depth));
+   let uv = (vec2<f32>(pixel) + 0.5) / size;
+   let view = params.inverse_projection * vec4(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+   return view.xyz / view.w;
+}
+
+// Polynomial approximation of Turbo by Anton Mikhailov
+fn turbo(x: f32) -> vec3<f32> {
+   let red = vec4(0.13572138, 4.61539260, -42.66032258, 132.13108234);
+   let green = vec4(0.09140261, 2.19418839, 4.84296658, -14.18503333);
+   let blue = vec4(0.10667330, 12.64194608, -60.58204836, 110.36276771);
+   let red2 = vec2(-152.94239396, 59.28637943);
+   let green2 = vec2(4.27729857, 2.82956604);
+   let blue2 = vec2(-89.90310912, 27.34824973);
+   let t = clamp(x, 0.0, 1.0);
+   let v4 = vec4(1.0, t, t * t, t * t * t);
+   let v2 = v4.zw * v4.z;
+   return vec3(
+      dot(v4, red) + dot(v2, red2),
+      dot(v4, green) + dot(v2, green2),
+      dot(v4, blue) + dot(v2, blue2),
+   );
+}
+
+// Polynomial fit of matplotlib's viridis
+fn viridis(x: f32) -> vec3<f32> {
+   let c0 = vec3(0.2777273272234177, 0.005407344544966578, 0.3340998053353061);
+   let c1 = vec3(0.1050930431085774, 1.404613529898575, 1.384590162594685);
+   let c2 = vec3(-0.3308618287255563, 0.214847559468213, 0.09509516302823659);
+   let c3 = vec3(-4.634230498983486, -5.799100973351585, -19.33244095627987);
+   let c4 = vec3(6.228269936347081, 14.17993336680509, 56.69055260068105);
+   let c5 = vec3(4.776384997670288, -13.74514537774601, -65.35303263337234);
+   let c6 = vec3(-5.435455855934631, 4.645852612178535, 26.3124352495832);
+   let t = clamp(x, 0.0, 1.0);
+   return c0 + t * (c1 + t * (c2 + t * (c3 + t * (c4 + t * (c5 + t * c6)))));
+}
+
+// View depth of a pixel, or a negative number if it's empty
+fn view_depth(pixel: vec2<i32>) -> f32 {
+   let depth = textureLoad(input_depth, pixel, 0).r;
+   if depth == 0.0 {
+      return -1.0;
+   }
+   return -view_position(pixel, depth).z;
+}
+
+// First pass of the depth views: find the range to stretch the colormap over
+@compute
+@workgroup_size(8, 8, 1)
+fn depth_range_pass(@builtin(global_invocation_id) global_id: vec3<u32>) {
+   let size: vec2<u32> = textureDimensions(input_depth);
+   if global_id.x >= size.x || global_id.y >= size.y {
+      return;
+   }
+   let depth = view_depth(vec2<i32>(global_id.xy));
+   // Positive floats sort the same as their bits
+   if depth > 0.0 {
+      atomicMin(&depth_range[0], bitcast<u32>(depth));
+      atomicMax(&depth_range[1], bitcast<u32>(depth));
+   }
+}
+
+// 1 at the nearest point in the frame, 0 at the furthest
+fn nearness(pixel: vec2<i32>) -> f32 {
+   let near = bitcast<f32>(atomicLoad(&depth_range[0]));
+   let far = bitcast<f32>(atomicLoad(&depth_range[1]));
+   return 1.0 - (view_depth(pixel) - near) / max(far - near, 1e-6);
+}
+
+@compute
+@workgroup_size(8, 8, 1)
+fn depth_turbo(@builtin(global_invocation_id) global_id: vec3<u32>) {
+   let size: vec2<u32> = textureDimensions(input_depth);
+   if global_id.x >= size.x || global_id.y >= size.y {
+      return;
+   }
+   let pixel = vec2<i32>(global_id.xy);
+   if view_depth(pixel) < 0.0 {
+      store(global_id.xy, vec3(0.0));
+      return;
+   }
+   store(global_id.xy, turbo(nearness(pixel)));
+}
+
+@compute
+@workgroup_size(8, 8, 1)
+fn depth_viridis(@builtin(global_invocation_id) global_id: vec3<u32>) {
+   let size: vec2<u32> = textureDimensions(input_depth);
+   if global_id.x >= size.x || global_id.y >= size.y {
+      return;
+   }
+   let pixel = vec2<i32>(global_id.xy);
+   if view_depth(pixel) < 0.0 {
+      store(global_id.xy, vec3(0.0));
+      return;
+   }
+   store(global_id.xy, viridis(nearness(pixel)));
+}
+
+// Black where a point landed, white where filling covered a hole and red where it's still empty
+@compute
+@workgroup_size(8, 8, 1)
+fn holes(@builtin(global_invocation_id) global_id: vec3<u32>) {
+   let size: vec2<u32> = textureDimensions(input_depth);
+   if global_id.x >= size.x || global_id.y >= size.y {
+      return;
+   }
+   let pixel = vec2<i32>(global_id.xy);
+   if textureLoad(input_depth, pixel, 0).r == 0.0 {
+      store(global_id.xy, vec3(1.0, 0.0, 0.0));
+   } else if textureLoad(raster_depth, pixel, 0).r == 0.0 {
+      store(global_id.xy, vec3(1.0));
+   } else {
+      store(global_id.xy, vec3(0.0));
+   }
+}
+
+// View space normals from neighboring depths, taking whichever neighbor on each axis is closer in depth so
+// silhouettes don't smear. Mapped from -1..1 to 0..1 like a normal map
+@compute
+@workgroup_size(8, 8, 1)
+fn normals(@builtin(global_invocation_id) global_id: vec3<u32>) {
+   let size: vec2<u32> = textureDimensions(input_depth);
+   if global_id.x >= size.x || global_id.y >= size.y {
+      return;
+   }
+   let pixel = vec2<i32>(global_id.xy);
+   let depth = textureLoad(input_depth, pixel, 0).r;
+   if depth == 0.0 {
+      store(global_id.xy, vec3(0.0));
+      return;
+   }
+   let max_coords = vec2<i32>(size) - 1;
+   let center = view_position(pixel, depth);
+   var tangents: array<vec3<f32>, 2>;
+   for (var axis = 0; axis < 2; axis++) {
+      var step = vec2(0, 0);
+      step[axis] = 1;
+      let forward_pixel = min(pixel + step, max_coords);
+      let back_pixel = max(pixel - step, vec2(0));
+      let forward_depth = textureLoad(input_depth, forward_pixel, 0).r;
+      let back_depth = textureLoad(input_depth, back_pixel, 0).r;
+      let forward = view_position(forward_pixel, forward_depth) - center;
+      let back = center - view_position(back_pixel, back_depth);
+      let use_forward = forward_depth != 0.0
+         && (back_depth == 0.0 || abs(forward_depth - depth) <= abs(back_depth - depth));
+      if use_forward {
+         tangents[axis] = forward;
+      } else if back_depth != 0.0 {
+         tangents[axis] = back;
+      } else {
+         store(global_id.xy, vec3(0.5, 0.5, 1.0));
+         return;
+      }
+   }
+   // x runs right and y runs down the image, so this points towards the camera
+   let normal = normalize(cross(tangents[1], tangents[0]));
+   store(global_id.xy, normal * 0.5 + 0.5);
+}
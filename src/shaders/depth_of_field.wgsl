@@ -0,0 +1,82 @@
+struct Params {
+   // Unprojects the depth target back into view space
+   inverse_projection: mat4x4<f32>,
+   focus: f32,
+   // Blur radius in pixels of points infinitely far away
+   aperture: f32,
+   max_radius: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> params: Params;
+@group(0) @binding(1)
+var input_image: texture_2d<f32>;
+@group(0) @binding(2)
+var input_depth: texture_2d<f32>;
+@group(0) @binding(3)
+var output_image: texture_storage_2d<rgba16float, write>;
+
+const SAMPLES: u32 = 48u;
+const GOLDEN_ANGLE: f32 = 2.39996323;
+
+// Distance along the view axis of whatever is at this pixel
+fn view_depth(pixel: vec2<i32>, depth: f32) -> f32 {
+   let size = vec2<f32>(textureDimensions(input_depth));
+   let uv = (vec2<f32>(pixel) + 0.5) / size;
+   let ndc = vec4(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+   let view = params.inverse_projection * ndc;
+   return -view.z / view.w;
+}
+
+// Thin lens circle of confusion, in pixels
+fn coc(pixel: vec2<i32>, depth: f32) -> f32 {
+   let distance = max(view_depth(pixel, depth), 1e-4);
+   return min(params.aperture * abs(distance - params.focus) / distance, params.max_radius);
+}
+
+// Gathers over the center pixel's circle of confusion. Samples nearer than the center only count where
+// their own blur reaches it, and samples further away are cut off at the center's blur, so sharp edges
+// don't pick up halos from what's behind or in front of them
+@compute
+@workgroup_size(8, 8, 1)
+fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+   let size: vec2<u32> = textureDimensions(input_image);
+   if global_id.x >= size.x || global_id.y >= size.y {
+      return;
+   }
+   let center = vec2<i32>(global_id.xy);
+   let color = textureLoad(input_image, center, 0);
+   let depth = textureLoad(input_depth, center, 0).r;
+   // Empty pixels are left to the background
+   if depth == 0.0 {
+      textureStore(output_image, global_id.xy, color);
+      return;
+   }
+   let radius = coc(center, depth);
+   if radius < 0.5 {
+      textureStore(output_image, global_id.xy, color);
+      return;
+   }
+
+   let max_coords = vec2<i32>(size) - 1;
+   var sum = color.rgb;
+   var weight = 1.0;
+   for (var i = 0u; i < SAMPLES; i++) {
+      let r = radius * sqrt((f32(i) + 0.5) / f32(SAMPLES));
+      let angle = f32(i) * GOLDEN_ANGLE;
+      let offset = vec2(cos(angle), sin(angle)) * r;
+      let pixel = clamp(center + vec2<i32>(round(offset)), vec2(0), max_coords);
+      let sample_depth = textureLoad(input_depth, pixel, 0).r;
+      if sample_depth == 0.0 {
+         continue;
+      }
+      var sample_radius = coc(pixel, sample_depth);
+      if sample_depth > depth {
+         sample_radius = min(sample_radius, radius);
+      }
+      let w = clamp(sample_radius - r + 1.0, 0.0, 1.0);
+      sum += textureLoad(input_image, pixel, 0).rgb * w;
+      weight += w;
+   }
+   textureStore(output_image, global_id.xy, vec4(sum / weight, color.a));
+}
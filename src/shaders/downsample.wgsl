@@ -0,0 +1,66 @@
+@group(0) @binding(0)
+var input_image: texture_2d<f32>;
+@group(0) @binding(1)
+var output_image: texture_storage_2d<bgra8unorm, write>;
+@group(0) @binding(2)
+var input_depth: texture_2d<f32>;
+@group(0) @binding(3)
+var output_depth: texture_storage_2d<r32float, write>;
+
+struct Background {
+   // Linear color
+   color: vec4<f32>,
+   // 0: color, 1: backdrop, 2: transparent
+   mode: u32,
+}
+
+@group(0) @binding(4)
+var<uniform> background: Background;
+@group(0) @binding(5)
+var backdrop: texture_2d<f32>;
+@group(0) @binding(6)
+var backdrop_sampler: sampler;
+
+fn linear_to_srgb(linear: vec3<f32>) -> vec3<f32> {
+   let low = linear * 12.92;
+   let high = 1.055 * pow(linear, vec3(1.0 / 2.4)) - 0.055;
+   return select(high, low, linear <= vec3(0.0031308));
+}
+
+// Box filter each factor x factor block of the supersampled image into one output pixel
+@compute
+@workgroup_size(8, 8, 1)
+fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+   let size: vec2<u32> = textureDimensions(output_image);
+   if global_id.x >= size.x || global_id.y >= size.y {
+      return;
+   }
+   let factor = textureDimensions(input_image).x / size.x;
+   let origin = global_id.xy * factor;
+   var color = vec4<f32>(0.0);
+   for (var y = 0u; y < factor; y++) {
+      for (var x = 0u; x < factor; x++) {
+         color += textureLoad(input_image, origin + vec2(x, y), 0);
+      }
+   }
+   color /= f32(factor * factor);
+
+   // Internally alpha marks holes (1 is nothing rendered), so after averaging it's the fraction of the pixel
+   // that's empty. Holes are cleared to black, so rgb is already premultiplied by the covered fraction
+   let empty = color.a;
+   var rgb = color.rgb;
+   var alpha = 1.0;
+   if background.mode == 0u {
+      rgb += empty * background.color.rgb;
+   } else if background.mode == 1u {
+      let uv = (vec2<f32>(global_id.xy) + 0.5) / vec2<f32>(size);
+      rgb += empty * textureSampleLevel(backdrop, backdrop_sampler, uv, 0.0).rgb;
+   } else {
+      alpha = 1.0 - empty;
+      rgb = select(vec3(0.0), rgb / alpha, alpha > 0.0);
+   }
+   // Filtering happens in linear space, the output is sRGB encoded
+   textureStore(output_image, global_id.xy, vec4(linear_to_srgb(clamp(rgb, vec3(0.0), vec3(1.0))), alpha));
+   // Averaging depth across an edge would invent surfaces in between, so take the center sample instead
+   textureStore(output_depth, global_id.xy, textureLoad(input_depth, origin + factor / 2u, 0));
+}
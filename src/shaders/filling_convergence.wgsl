@@ -0,0 +1,39 @@
+struct Convergence {
+   changed: u32,
+   empty: u32,
+}
+
+// What the last iteration that ran left behind
+struct Status {
+   iterations: u32,
+   changed: u32,
+   empty: u32,
+}
+
+@group(0) @binding(0)
+var<storage, read_write> convergence: Convergence;
+// Workgroup counts for the next iteration's indirect dispatch
+@group(0) @binding(1)
+var<storage, read_write> dispatch: array<u32, 3>;
+@group(0) @binding(2)
+var<storage, read_write> status: Status;
+
+// Runs after every filling iteration. Once an iteration changes nothing, the next ones would only copy the
+// same pixels back and forth, so they're dispatched with no workgroups. Both ping-pong textures hold the
+// same image by then, so it doesn't matter which one is read
+@compute
+@workgroup_size(1, 1, 1)
+fn main() {
+   if dispatch[0] != 0u {
+      status.iterations += 1u;
+      status.changed = convergence.changed;
+      status.empty = convergence.empty;
+      if convergence.changed == 0u {
+         dispatch[0] = 0u;
+         dispatch[1] = 0u;
+         dispatch[2] = 0u;
+      }
+   }
+   convergence.changed = 0u;
+   convergence.empty = 0u;
+}
@@ -0,0 +1,40 @@
+@group(0) @binding(0)
+var raster_depth: texture_2d<f32>;
+@group(0) @binding(1)
+var background_depth: texture_2d<f32>;
+@group(0) @binding(2)
+var final_depth: texture_2d<f32>;
+@group(0) @binding(3)
+var mask: texture_storage_2d<rgba8unorm, write>;
+@group(0) @binding(4)
+var final_image: texture_2d<f32>;
+
+// Classify each output pixel by comparing depth after every stage. A stage that was skipped is given the
+// previous stage's depth, so it never claims a pixel. Depth 0 means nothing was rasterized there. The
+// occlusion shader pushes holes far back instead, so emptiness at the end comes from the alpha flag
+@compute
+@workgroup_size(8, 8, 1)
+fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+   let size: vec2<u32> = textureDimensions(mask);
+   if global_id.x >= size.x || global_id.y >= size.y {
+      return;
+   }
+   // Supersampled renders are classified by the center sample of each block, like the depth readback
+   let factor = textureDimensions(raster_depth).x / size.x;
+   let coords = global_id.xy * factor + factor / 2u;
+   let raster = textureLoad(raster_depth, coords, 0).r;
+   let background = textureLoad(background_depth, coords, 0).r;
+   let final_value = textureLoad(final_depth, coords, 0).r;
+   let empty = textureLoad(final_image, coords, 0).a == 1.0;
+
+   // 0: original point, 1: background filling, 2: occlusion filling, 3: still empty
+   var provenance = 0.0;
+   if empty {
+      provenance = 3.0;
+   } else if final_value != background {
+      provenance = 2.0;
+   } else if abs(raster) < 1e-9 {
+      provenance = 1.0;
+   }
+   textureStore(mask, global_id.xy, vec4(provenance / 3.0, 0.0, 0.0, 1.0));
+}
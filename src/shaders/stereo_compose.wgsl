@@ -0,0 +1,57 @@
+@group(0) @binding(0)
+var left_image: texture_2d<f32>;
+@group(0) @binding(1)
+var right_image: texture_2d<f32>;
+@group(0) @binding(2)
+var output_image: texture_storage_2d<rgba16float, write>;
+
+// Average two neighboring pixels when squeezing an eye into half the output
+fn load_pair(left: bool, a: vec2<i32>, b: vec2<i32>) -> vec4<f32> {
+   let size: vec2<u32> = textureDimensions(left_image);
+   let max_coords = vec2<i32>(size) - 1;
+   if left {
+      return (textureLoad(left_image, min(a, max_coords), 0) + textureLoad(left_image, min(b, max_coords), 0)) * 0.5;
+   }
+   return (textureLoad(right_image, min(a, max_coords), 0) + textureLoad(right_image, min(b, max_coords), 0)) * 0.5;
+}
+
+@compute
+@workgroup_size(8, 8, 1)
+fn side_by_side(@builtin(global_invocation_id) global_id: vec3<u32>) {
+   let size: vec2<u32> = textureDimensions(output_image);
+   if global_id.x >= size.x || global_id.y >= size.y {
+      return;
+   }
+   let half_width = i32(size.x / 2u);
+   let id = vec2<i32>(global_id.xy);
+   let left = id.x < half_width;
+   let x = select(id.x - half_width, id.x, left) * 2;
+   textureStore(output_image, global_id.xy, load_pair(left, vec2(x, id.y), vec2(x + 1, id.y)));
+}
+
+@compute
+@workgroup_size(8, 8, 1)
+fn top_bottom(@builtin(global_invocation_id) global_id: vec3<u32>) {
+   let size: vec2<u32> = textureDimensions(output_image);
+   if global_id.x >= size.x || global_id.y >= size.y {
+      return;
+   }
+   let half_height = i32(size.y / 2u);
+   let id = vec2<i32>(global_id.xy);
+   let left = id.y < half_height;
+   let y = select(id.y - half_height, id.y, left) * 2;
+   textureStore(output_image, global_id.xy, load_pair(left, vec2(id.x, y), vec2(id.x, y + 1)));
+}
+
+// Red/cyan: red channel from the left eye, green and blue from the right
+@compute
+@workgroup_size(8, 8, 1)
+fn anaglyph(@builtin(global_invocation_id) global_id: vec3<u32>) {
+   let size: vec2<u32> = textureDimensions(output_image);
+   if global_id.x >= size.x || global_id.y >= size.y {
+      return;
+   }
+   let left = textureLoad(left_image, global_id.xy, 0);
+   let right = textureLoad(right_image, global_id.xy, 0);
+   textureStore(output_image, global_id.xy, vec4(left.r, right.g, right.b, max(left.a, right.a)));
+}
@@ -0,0 +1,151 @@
+use crate::texture::Texture;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum StereoMode {
+    SideBySide,
+    TopBottom,
+    Anaglyph,
+}
+
+impl StereoMode {
+    fn entry_point(&self) -> &'static str {
+        match self {
+            StereoMode::SideBySide => "side_by_side",
+            StereoMode::TopBottom => "top_bottom",
+            StereoMode::Anaglyph => "anaglyph",
+        }
+    }
+}
+
+// Interaxial distance is in scene units (the image spans -1..1). Convergence is the distance from the eye
+// at which the two views line up, i.e. what appears at screen depth
+#[derive(Copy, Clone, Debug)]
+pub struct Stereo {
+    pub mode: StereoMode,
+    pub interaxial: f32,
+    pub convergence: f32,
+}
+
+// Holds both eye renders and composes them into a single frame
+pub struct StereoCompositor {
+    pub textures: [Texture; 2],
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipelines: Vec<(StereoMode, wgpu::ComputePipeline)>,
+}
+
+impl StereoCompositor {
+    pub fn new(device: &wgpu::Device, dims: (u32, u32)) -> Self {
+        let create_texture = |label| {
+            Texture::new(
+                device,
+                dims,
+                crate::renderer::WORKING_FORMAT,
+                wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::TEXTURE_BINDING,
+                label,
+            )
+        };
+        let textures = [create_texture("left_eye"), create_texture("right_eye")];
+
+        let input_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Stereo Bindgroup Layout"),
+            entries: &[
+                input_entry(0),
+                input_entry(1),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: crate::renderer::WORKING_FORMAT,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let shader =
+            device.create_shader_module(wgpu::include_wgsl!("shaders/stereo_compose.wgsl"));
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("stereo_compose_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipelines = [
+            StereoMode::SideBySide,
+            StereoMode::TopBottom,
+            StereoMode::Anaglyph,
+        ]
+        .into_iter()
+        .map(|mode| {
+            let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(mode.entry_point()),
+                module: &shader,
+                layout: Some(&layout),
+                entry_point: mode.entry_point(),
+                compilation_options: Default::default(),
+                cache: None,
+            });
+            (mode, pipeline)
+        })
+        .collect();
+
+        StereoCompositor {
+            textures,
+            bind_group_layout,
+            pipelines,
+        }
+    }
+
+    // Compose textures[0] (left) and textures[1] (right) into output
+    pub fn run(
+        &self,
+        device: &wgpu::Device,
+        command_encoder: &mut wgpu::CommandEncoder,
+        output: &Texture,
+        mode: StereoMode,
+    ) {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Stereo Bindgroup"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&self.textures[0].texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&self.textures[1].texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&output.texture_view),
+                },
+            ],
+        });
+        let pipeline = &self
+            .pipelines
+            .iter()
+            .find(|(pipeline_mode, _)| *pipeline_mode == mode)
+            .unwrap()
+            .1;
+        let dims = (output.texture.width(), output.texture.height());
+        let mut compute_pass = command_encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("stereo_compose_pass"),
+            ..Default::default()
+        });
+        compute_pass.set_pipeline(pipeline);
+        compute_pass.set_bind_group(0, &bind_group, &[]);
+        compute_pass.dispatch_workgroups(dims.0.div_ceil(8), dims.1.div_ceil(8), 1);
+    }
+}
@@ -34,4 +34,27 @@ impl Texture {
             texture_view,
         }
     }
+
+    // Copy the whole texture into dst, which must have the same size and format
+    pub fn copy_to(&self, command_encoder: &mut wgpu::CommandEncoder, dst: &wgpu::Texture) {
+        command_encoder.copy_texture_to_texture(
+            wgpu::ImageCopyTexture {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyTexture {
+                texture: dst,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::Extent3d {
+                width: dst.width(),
+                height: dst.height(),
+                depth_or_array_layers: 1,
+            },
+        );
+    }
 }
@@ -0,0 +1,141 @@
+use std::path::{Path, PathBuf};
+
+use nalgebra::Matrix4;
+use serde::{Deserialize, Serialize};
+
+use crate::view_params::ViewParams;
+
+// NeRF style transforms.json. transform_matrix is camera-to-world in OpenGL convention (x right, y up, looking down -z).
+// view_matrix and projection_matrix are exactly what gets multiplied together and uploaded to the raster shader
+#[derive(Serialize, Deserialize, Default)]
+pub struct Transforms {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub camera_angle_x: Option<f32>,
+    // Datasets may instead give shared intrinsics here, which frames can override
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fl_x: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fl_y: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cx: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cy: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub w: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub h: Option<u32>,
+    pub frames: Vec<Frame>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Frame {
+    pub file_path: String,
+    pub transform_matrix: [[f32; 4]; 4],
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub view_matrix: Option<[[f32; 4]; 4]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub projection_matrix: Option<[[f32; 4]; 4]>,
+    // Pinhole intrinsics in pixels, only present for perspective projections
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fl_x: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fl_y: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cx: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cy: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub w: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub h: Option<u32>,
+}
+
+// nalgebra is column major, transforms.json is row major
+fn rows(matrix: &Matrix4<f32>) -> [[f32; 4]; 4] {
+    let mut rows = [[0.0; 4]; 4];
+    for (r, row) in rows.iter_mut().enumerate() {
+        for (c, value) in row.iter_mut().enumerate() {
+            *value = matrix[(r, c)];
+        }
+    }
+    rows
+}
+
+pub fn from_rows(rows: &[[f32; 4]; 4]) -> Matrix4<f32> {
+    Matrix4::from_fn(|r, c| rows[r][c])
+}
+
+impl Frame {
+    pub fn new(file_path: &str, view_params: &ViewParams, (width, height): (u32, u32)) -> Frame {
+        let projection = view_params.wgpu_projection();
+        let (w, h) = (width as f32, height as f32);
+        // A perspective matrix has -1 in the bottom row (w = -z), orthographic has no focal length
+        let intrinsics = if projection[(3, 2)] != 0.0 {
+            Some((
+                projection[(0, 0)] * w / 2.0,
+                projection[(1, 1)] * h / 2.0,
+                w / 2.0 * (1.0 - projection[(0, 2)]),
+                h / 2.0 * (1.0 + projection[(1, 2)]),
+            ))
+        } else {
+            None
+        };
+        Frame {
+            file_path: file_path.to_owned(),
+            transform_matrix: rows(
+                &view_params
+                    .camera
+                    .try_inverse()
+                    .unwrap_or_else(Matrix4::identity),
+            ),
+            view_matrix: Some(rows(&view_params.camera)),
+            projection_matrix: Some(rows(&projection)),
+            fl_x: intrinsics.map(|i| i.0),
+            fl_y: intrinsics.map(|i| i.1),
+            cx: intrinsics.map(|i| i.2),
+            cy: intrinsics.map(|i| i.3),
+            w: Some(width),
+            h: Some(height),
+        }
+    }
+}
+
+// A transforms.json that's rewritten every time a frame is added, so a batch can be interrupted at any point
+pub struct TransformsFile {
+    path: PathBuf,
+    transforms: Transforms,
+}
+
+impl TransformsFile {
+    pub fn new(path: &str) -> Self {
+        TransformsFile {
+            path: PathBuf::from(path),
+            transforms: Transforms::default(),
+        }
+    }
+
+    pub fn add_frame(
+        &mut self,
+        image_path: &str,
+        view_params: &ViewParams,
+        size: (u32, u32),
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        // file_path is relative to the transforms.json, as NeRF loaders expect
+        let relative_path = self
+            .path
+            .parent()
+            .and_then(|dir| Path::new(image_path).strip_prefix(dir).ok())
+            .and_then(Path::to_str)
+            .unwrap_or(image_path);
+        let frame = Frame::new(relative_path, view_params, size);
+        if self.transforms.camera_angle_x.is_none() {
+            self.transforms.camera_angle_x = frame
+                .fl_x
+                .map(|fl_x| 2.0 * (size.0 as f32 / (2.0 * fl_x)).atan());
+        }
+        self.transforms.frames.push(frame);
+        let file = std::fs::File::create(&self.path)?;
+        serde_json::to_writer_pretty(file, &self.transforms)?;
+        Ok(())
+    }
+}
@@ -1,33 +1,304 @@
 use nalgebra::{Matrix4, Point3, Vector3};
+use serde::{Deserialize, Serialize};
 
-#[derive(Copy, Clone)]
+#[rustfmt::skip]
+pub const OPENGL_TO_WGPU_MATRIX: Matrix4<f32> = Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 0.5, 0.5,
+    0.0, 0.0, 0.0, 1.0,
+);
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Projection {
+    Orthographic {
+        left: f32,
+        right: f32,
+        bottom: f32,
+        top: f32,
+        near: f32,
+        far: f32,
+    },
+    Perspective {
+        fovy: f32,
+        aspect: f32,
+        near: f32,
+        far: f32,
+    },
+    // Asymmetric perspective frustum like glFrustum, edges are given on the near plane
+    Frustum {
+        left: f32,
+        right: f32,
+        bottom: f32,
+        top: f32,
+        near: f32,
+        far: f32,
+    },
+}
+
+impl Projection {
+    pub fn matrix(&self) -> Matrix4<f32> {
+        match *self {
+            Projection::Orthographic {
+                left,
+                right,
+                bottom,
+                top,
+                near,
+                far,
+            } => Matrix4::new_orthographic(left, right, bottom, top, near, far),
+            Projection::Perspective {
+                fovy,
+                aspect,
+                near,
+                far,
+            } => Matrix4::new_perspective(aspect, fovy, near, far),
+            Projection::Frustum {
+                left,
+                right,
+                bottom,
+                top,
+                near,
+                far,
+            } => {
+                #[rustfmt::skip]
+                let matrix = Matrix4::new(
+                    2.0 * near / (right - left), 0.0, (right + left) / (right - left), 0.0,
+                    0.0, 2.0 * near / (top - bottom), (top + bottom) / (top - bottom), 0.0,
+                    0.0, 0.0, -(far + near) / (far - near), -2.0 * far * near / (far - near),
+                    0.0, 0.0, -1.0, 0.0,
+                );
+                matrix
+            }
+        }
+    }
+
+    // Build a frustum from pinhole intrinsics in pixels (image y pointing down, as in COLMAP/OpenCV)
+    pub fn from_intrinsics(
+        (fx, fy): (f32, f32),
+        (cx, cy): (f32, f32),
+        (width, height): (f32, f32),
+        near: f32,
+        far: f32,
+    ) -> Projection {
+        Projection::Frustum {
+            left: -cx / fx * near,
+            right: (width - cx) / fx * near,
+            bottom: -(height - cy) / fy * near,
+            top: cy / fy * near,
+            near,
+            far,
+        }
+    }
+
+    // Magnify the image by factor without moving the camera
+    pub fn zoomed(&self, factor: f32) -> Projection {
+        match *self {
+            Projection::Orthographic {
+                left,
+                right,
+                bottom,
+                top,
+                near,
+                far,
+            } => Projection::Orthographic {
+                left: left / factor,
+                right: right / factor,
+                bottom: bottom / factor,
+                top: top / factor,
+                near,
+                far,
+            },
+            Projection::Perspective {
+                fovy,
+                aspect,
+                near,
+                far,
+            } => Projection::Perspective {
+                fovy: 2.0 * ((fovy / 2.0).tan() / factor).atan(),
+                aspect,
+                near,
+                far,
+            },
+            Projection::Frustum {
+                left,
+                right,
+                bottom,
+                top,
+                near,
+                far,
+            } => Projection::Frustum {
+                left: left / factor,
+                right: right / factor,
+                bottom: bottom / factor,
+                top: top / factor,
+                near,
+                far,
+            },
+        }
+    }
+}
+
+// A physical screen for head-coupled (off-axis) rendering. The screen plane stays fixed and the
+// frustum is skewed through its corners from wherever the eye is (Kooima, "Generalized Perspective Projection")
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct Screen {
+    pub lower_left: [f32; 3],
+    pub lower_right: [f32; 3],
+    pub upper_left: [f32; 3],
+    pub near: f32,
+    pub far: f32,
+}
+
+impl Screen {
+    // Screen right, up and normal vectors
+    fn basis(&self) -> (Vector3<f32>, Vector3<f32>, Vector3<f32>) {
+        let lower_left = Vector3::from(self.lower_left);
+        let right = (Vector3::from(self.lower_right) - lower_left).normalize();
+        let up = (Vector3::from(self.upper_left) - lower_left).normalize();
+        (right, up, right.cross(&up).normalize())
+    }
+
+    pub fn frustum(&self, eye: &Point3<f32>) -> Projection {
+        let (right, up, normal) = self.basis();
+        let to_lower_left = Point3::from(self.lower_left) - eye;
+        let to_lower_right = Point3::from(self.lower_right) - eye;
+        let to_upper_left = Point3::from(self.upper_left) - eye;
+        // Distance from the eye to the screen plane
+        let distance = -to_lower_left.dot(&normal);
+        let scale = self.near / distance;
+        Projection::Frustum {
+            left: right.dot(&to_lower_left) * scale,
+            right: right.dot(&to_lower_right) * scale,
+            bottom: up.dot(&to_lower_left) * scale,
+            top: up.dot(&to_upper_left) * scale,
+            near: self.near,
+            far: self.far,
+        }
+    }
+
+    // Camera looking straight at the screen plane from eye
+    pub fn view(&self, eye: &Point3<f32>) -> Matrix4<f32> {
+        let (right, up, normal) = self.basis();
+        #[rustfmt::skip]
+        let rotation = Matrix4::new(
+            right.x, right.y, right.z, 0.0,
+            up.x, up.y, up.z, 0.0,
+            normal.x, normal.y, normal.z, 0.0,
+            0.0, 0.0, 0.0, 1.0,
+        );
+        rotation * Matrix4::new_translation(&-eye.coords)
+    }
+}
+
+fn default_up() -> [f32; 3] {
+    [0.0, 1.0, 0.0]
+}
+
+// On-disk representation of ViewParams. The camera matrix is derived, so it's rebuilt on load
+#[derive(Serialize, Deserialize)]
+struct SavedView {
+    eye: [f32; 3],
+    look_at: [f32; 3],
+    #[serde(default = "default_up")]
+    up: [f32; 3],
+    roll: f32,
+    pitch: f32,
+    yaw: f32,
+    projection: Projection,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    screen: Option<Screen>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    focus: Option<f32>,
+}
+
+impl From<ViewParams> for SavedView {
+    fn from(view_params: ViewParams) -> SavedView {
+        SavedView {
+            eye: view_params.eye.into(),
+            look_at: view_params.look_at.into(),
+            up: view_params.up.into(),
+            roll: view_params.roll,
+            pitch: view_params.pitch,
+            yaw: view_params.yaw,
+            projection: view_params.projection,
+            screen: view_params.screen,
+            focus: view_params.focus,
+        }
+    }
+}
+
+impl From<SavedView> for ViewParams {
+    fn from(saved: SavedView) -> ViewParams {
+        let mut view_params =
+            ViewParams::new(saved.eye.into(), saved.look_at.into(), saved.projection);
+        view_params.up = saved.up.into();
+        view_params.roll = saved.roll;
+        view_params.pitch = saved.pitch;
+        view_params.yaw = saved.yaw;
+        view_params.screen = saved.screen;
+        view_params.focus = saved.focus;
+        view_params.update_camera();
+        view_params
+    }
+}
+
+#[derive(Copy, Clone, Serialize, Deserialize)]
+#[serde(from = "SavedView", into = "SavedView")]
 pub struct ViewParams {
     eye: Point3<f32>,
     look_at: Point3<f32>,
+    up: Vector3<f32>,
     roll: f32,
     pitch: f32,
     yaw: f32,
+    projection: Projection,
+    // When set, camera and projection follow the eye through this screen instead of using look_at and projection
+    screen: Option<Screen>,
+    // Depth of the plane that's sharp with depth of field on, along the view axis. None follows look_at
+    focus: Option<f32>,
+    // Extra x/y scale that fits the projection into an output whose aspect differs from the input's.
+    // It belongs to the renderer rather than the view, so it isn't saved
+    viewport: [f32; 2],
     pub camera: Matrix4<f32>,
-    pub projection: Matrix4<f32>,
 }
 
 impl ViewParams {
-    pub fn new(eye: Point3<f32>, look_at: Point3<f32>, projection: Matrix4<f32>) -> Self {
+    pub fn new(eye: Point3<f32>, look_at: Point3<f32>, projection: Projection) -> Self {
         ViewParams {
             eye,
             look_at,
+            up: Vector3::new(0.0, 1.0, 0.0),
             roll: 0.0,
             pitch: 0.0,
             yaw: 0.0,
+            projection,
+            screen: None,
+            focus: None,
+            viewport: [1.0, 1.0],
             camera: Matrix4::look_at_rh(&eye, &look_at, &Vector3::new(0.0, 1.0, 0.0))
                 * Matrix4::from_euler_angles(0.0, 0.0, 0.0),
-            projection,
         }
     }
 
+    // Recover eye, look_at and up from a world-to-camera matrix in OpenGL convention
+    pub fn from_view_matrix(view: &Matrix4<f32>, projection: Projection) -> Option<Self> {
+        let camera_to_world = view.try_inverse()?;
+        let eye = camera_to_world.transform_point(&Point3::origin());
+        let forward = camera_to_world.transform_vector(&-Vector3::z());
+        let mut view_params = ViewParams::new(eye, eye + forward, projection);
+        view_params.up = camera_to_world.transform_vector(&Vector3::y());
+        view_params.update_camera();
+        Some(view_params)
+    }
+
     fn update_camera(&mut self) {
-        self.camera = Matrix4::look_at_rh(&self.eye, &self.look_at, &Vector3::new(0.0, 1.0, 0.0))
-            * Matrix4::from_euler_angles(self.roll, self.pitch, self.yaw);
+        let view = match &self.screen {
+            Some(screen) => screen.view(&self.eye),
+            None => Matrix4::look_at_rh(&self.eye, &self.look_at, &self.up),
+        };
+        self.camera = view * Matrix4::from_euler_angles(self.roll, self.pitch, self.yaw);
     }
 
     pub fn set_eye(&mut self, eye: Point3<f32>) {
@@ -35,8 +306,23 @@ impl ViewParams {
         self.update_camera();
     }
 
-    pub fn set_look_at(&mut self, look_at: Point3<f32>) {
-        self.look_at = look_at;
+    // Move eye and look_at together along the camera's right, up and forward axes, taken from the inverse
+    // of the camera so they follow the rotation and the screen's orientation in off-axis mode. Eye and
+    // look_at are in the frame the scene is in after roll/pitch/yaw, so the axes are rotated into it
+    pub fn translate(&mut self, right: f32, up: f32, forward: f32) {
+        let Some(camera_to_world) = self.camera.try_inverse() else {
+            return;
+        };
+        let rotation = Matrix4::from_euler_angles(self.roll, self.pitch, self.yaw);
+        let axis = |axis: Vector3<f32>| {
+            rotation
+                .transform_vector(&camera_to_world.transform_vector(&axis))
+                .normalize()
+        };
+        let offset =
+            axis(Vector3::x()) * right + axis(Vector3::y()) * up + axis(-Vector3::z()) * forward;
+        self.eye += offset;
+        self.look_at += offset;
         self.update_camera();
     }
 
@@ -55,6 +341,37 @@ impl ViewParams {
         self.update_camera();
     }
 
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.projection = projection;
+    }
+
+    pub fn set_screen(&mut self, screen: Option<Screen>) {
+        self.screen = screen;
+        self.update_camera();
+    }
+
+    pub fn set_focus(&mut self, focus: Option<f32>) {
+        self.focus = focus;
+    }
+
+    // Focus depth in effect, the distance to look_at unless one was set
+    pub fn focus_distance(&self) -> f32 {
+        self.focus
+            .unwrap_or_else(|| (self.look_at - self.eye).magnitude())
+    }
+
+    pub fn eye(&self) -> Point3<f32> {
+        self.eye
+    }
+
+    pub fn look_at(&self) -> Point3<f32> {
+        self.look_at
+    }
+
+    pub fn up(&self) -> Vector3<f32> {
+        self.up
+    }
+
     pub fn roll(&self) -> f32 {
         self.roll
     }
@@ -66,4 +383,261 @@ impl ViewParams {
     pub fn yaw(&self) -> f32 {
         self.yaw
     }
+
+    // The projection in effect, which in off-axis mode depends on where the eye is
+    pub fn projection(&self) -> Projection {
+        match &self.screen {
+            Some(screen) => screen.frustum(&self.eye),
+            None => self.projection,
+        }
+    }
+
+    pub fn projection_matrix(&self) -> Matrix4<f32> {
+        self.projection().matrix()
+    }
+
+    // Projection as uploaded to the GPU (depth remapped from OpenGL's -1..1 to wgpu's 0..1)
+    pub fn wgpu_projection(&self) -> Matrix4<f32> {
+        let viewport =
+            Matrix4::new_nonuniform_scaling(&Vector3::new(self.viewport[0], self.viewport[1], 1.0));
+        OPENGL_TO_WGPU_MATRIX * viewport * self.projection_matrix()
+    }
+
+    pub fn with_viewport(&self, viewport: [f32; 2]) -> ViewParams {
+        ViewParams { viewport, ..*self }
+    }
+
+    // View from a camera moved sideways by offset (positive is to the right) that lines up with this
+    // view at convergence distance. Used for stereo pairs and multi-view sweeps
+    pub fn offset_eye(&self, offset: f32, convergence: f32) -> ViewParams {
+        let mut view_params = *self;
+        if let Some(screen) = &self.screen {
+            // The screen plane is already the zero parallax plane, just move the eye along it
+            let (right, _, _) = screen.basis();
+            view_params.set_eye(self.eye + right * offset);
+            return view_params;
+        }
+        match self.projection {
+            // Parallel projection has no parallax from moving sideways, so toe in towards the convergence point
+            Projection::Orthographic { .. } => {
+                view_params.camera = Matrix4::look_at_rh(
+                    &Point3::new(offset, 0.0, 0.0),
+                    &Point3::new(0.0, 0.0, -convergence),
+                    &Vector3::y(),
+                ) * self.camera;
+            }
+            // Parallel cameras with the frustum shifted back so the convergence plane stays put
+            Projection::Perspective { .. } | Projection::Frustum { .. } => {
+                let (left, right, bottom, top, near, far) = match self.projection {
+                    Projection::Perspective {
+                        fovy,
+                        aspect,
+                        near,
+                        far,
+                    } => {
+                        let top = near * (fovy / 2.0).tan();
+                        (-top * aspect, top * aspect, -top, top, near, far)
+                    }
+                    Projection::Frustum {
+                        left,
+                        right,
+                        bottom,
+                        top,
+                        near,
+                        far,
+                    } => (left, right, bottom, top, near, far),
+                    Projection::Orthographic { .. } => unreachable!(),
+                };
+                let shift = offset * near / convergence;
+                view_params.camera =
+                    Matrix4::new_translation(&Vector3::new(-offset, 0.0, 0.0)) * self.camera;
+                view_params.projection = Projection::Frustum {
+                    left: left - shift,
+                    right: right - shift,
+                    bottom,
+                    top,
+                    near,
+                    far,
+                };
+            }
+        }
+        view_params
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let file = std::fs::File::create(path)?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+
+    pub fn load(path: &str) -> Result<ViewParams, Box<dyn std::error::Error>> {
+        let file = std::fs::File::open(path)?;
+        Ok(serde_json::from_reader(std::io::BufReader::new(file))?)
+    }
+}
+
+// Views are saved by name as {views_dir}/{name}.json
+fn view_path(views_dir: &str, name: &str) -> String {
+    format!("{views_dir}/{name}.json")
+}
+
+pub fn save_named_view(
+    view_params: &ViewParams,
+    views_dir: &str,
+    name: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    std::fs::create_dir_all(views_dir)?;
+    view_params.save(&view_path(views_dir, name))
+}
+
+pub fn load_named_view(
+    views_dir: &str,
+    name: &str,
+) -> Result<ViewParams, Box<dyn std::error::Error>> {
+    ViewParams::load(&view_path(views_dir, name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn perspective() -> Projection {
+        Projection::Perspective {
+            fovy: 1.0,
+            aspect: 1.5,
+            near: 0.1,
+            far: 10.0,
+        }
+    }
+
+    fn assert_close(a: &Matrix4<f32>, b: &Matrix4<f32>) {
+        assert!((a - b).abs().max() < 1e-5, "{a} != {b}");
+    }
+
+    #[test]
+    fn saved_view_round_trip() {
+        let mut view_params = ViewParams::new(
+            Point3::new(0.5, -0.25, 2.0),
+            Point3::new(0.1, 0.0, 0.0),
+            perspective(),
+        );
+        view_params.set_roll(0.1);
+        view_params.set_pitch(-0.2);
+        view_params.set_yaw(0.3);
+        view_params.set_focus(Some(1.5));
+        let json = serde_json::to_string(&view_params).unwrap();
+        // The camera matrix is rebuilt on load rather than saved
+        assert!(!json.contains("camera"));
+        let loaded: ViewParams = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.eye(), view_params.eye());
+        assert_eq!(loaded.look_at(), view_params.look_at());
+        assert_eq!(
+            (loaded.roll(), loaded.pitch(), loaded.yaw()),
+            (0.1, -0.2, 0.3)
+        );
+        assert_eq!(loaded.focus_distance(), 1.5);
+        assert_close(&loaded.camera, &view_params.camera);
+        assert_close(
+            &loaded.projection_matrix(),
+            &view_params.projection_matrix(),
+        );
+    }
+
+    #[test]
+    fn intrinsics_map_pixels_to_ndc() {
+        let (width, height) = (400.0, 200.0);
+        let (fx, fy, cx, cy) = (200.0, 250.0, 100.0, 50.0);
+        let projection =
+            Projection::from_intrinsics((fx, fy), (cx, cy), (width, height), 1.0, 10.0);
+        let Projection::Frustum {
+            left,
+            right,
+            bottom,
+            top,
+            ..
+        } = projection
+        else {
+            panic!("expected a frustum");
+        };
+        assert_eq!((left, right, bottom, top), (-0.5, 1.5, -0.6, 0.2));
+        // A pixel's ray (image y points down) lands on that pixel in NDC at any depth
+        for (u, v, depth) in [(0.0, 0.0, 1.0), (cx, cy, 3.0), (300.0, 180.0, 7.0)] {
+            let point = Vector3::new((u - cx) / fx, -(v - cy) / fy, -1.0) * depth;
+            let clip = projection.matrix() * point.push(1.0);
+            let ndc = clip.xy() / clip.w;
+            assert!((ndc.x - (u / width * 2.0 - 1.0)).abs() < 1e-5);
+            assert!((ndc.y - (1.0 - v / height * 2.0)).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn screen_corners_fill_the_frustum() {
+        // A 2x1 screen turned 30 degrees about y, seen from off to one side
+        let rotation = Matrix4::from_euler_angles(0.0, 0.5, 0.0);
+        let corner = |x: f32, y: f32| -> [f32; 3] {
+            rotation
+                .transform_point(&Point3::new(x, y, 0.0))
+                .coords
+                .into()
+        };
+        let screen = Screen {
+            lower_left: corner(-1.0, -0.5),
+            lower_right: corner(1.0, -0.5),
+            upper_left: corner(-1.0, 0.5),
+            near: 0.1,
+            far: 10.0,
+        };
+        let mut view_params = ViewParams::new(Point3::origin(), Point3::origin(), perspective());
+        for eye in [Point3::new(0.0, 0.0, 2.0), Point3::new(0.7, -0.3, 1.2)] {
+            view_params.set_eye(eye);
+            view_params.set_screen(Some(screen));
+            let view_projection = view_params.projection_matrix() * view_params.camera;
+            for (x, y) in [(-1.0, -0.5), (1.0, -0.5), (-1.0, 0.5), (1.0, 0.5)] {
+                let clip = view_projection * Point3::from(corner(x, y)).to_homogeneous();
+                let ndc = clip.xy() / clip.w;
+                assert!((ndc.x - x.signum()).abs() < 1e-4, "{ndc} for {x}, {y}");
+                assert!((ndc.y - y.signum()).abs() < 1e-4, "{ndc} for {x}, {y}");
+            }
+        }
+    }
+
+    #[test]
+    fn translate_follows_the_camera_axes() {
+        let mut view_params =
+            ViewParams::new(Point3::new(0.3, 0.2, 2.0), Point3::origin(), perspective());
+        view_params.set_yaw(0.4);
+        view_params.set_pitch(-0.3);
+        view_params.set_roll(0.2);
+        let screen = Screen {
+            lower_left: [-1.0, -1.0, 0.0],
+            lower_right: [1.0, -1.0, 0.5],
+            upper_left: [-1.0, 1.0, 0.0],
+            near: 0.1,
+            far: 10.0,
+        };
+        for screen in [None, Some(screen)] {
+            view_params.set_screen(screen);
+            let before = view_params.camera;
+            view_params.translate(0.5, -0.25, 1.0);
+            // Everything shifts the opposite way in camera space, and comes closer moving forward
+            let point = Point3::new(0.1, 0.7, -0.4);
+            let shift = view_params.camera.transform_point(&point) - before.transform_point(&point);
+            assert!(
+                (shift - Vector3::new(-0.5, 0.25, 1.0)).magnitude() < 1e-5,
+                "{shift}"
+            );
+        }
+    }
+
+    #[test]
+    fn saved_view_defaults_up() {
+        let json = r#"{"eye":[0,0,2],"look_at":[0,0,0],"roll":0,"pitch":0,"yaw":0,
+            "projection":{"orthographic":{"left":-1,"right":1,"bottom":-1,"top":1,"near":0,"far":4}}}"#;
+        let loaded: ViewParams = serde_json::from_str(json).unwrap();
+        assert_eq!(loaded.up(), Vector3::y());
+        assert_close(
+            &loaded.camera,
+            &ViewParams::new(Point3::new(0.0, 0.0, 2.0), Point3::origin(), perspective()).camera,
+        );
+    }
 }
@@ -0,0 +1,112 @@
+use std::f32::consts::{FRAC_PI_2, PI};
+
+use image::{imageops, ImageBuffer, Rgba};
+use nalgebra::{Matrix4, Rotation3, Vector3};
+
+use crate::equirect;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum VrLayout {
+    // Front hemisphere per eye, left and right side by side
+    Vr180,
+    // Full sphere per eye, left on top of right
+    Vr360,
+}
+
+// Interaxial is in scene units like Stereo's. resolution is the height of each eye's equirect image
+#[derive(Copy, Clone, Debug)]
+pub struct Vr {
+    pub layout: VrLayout,
+    pub interaxial: f32,
+    pub resolution: u32,
+}
+
+// The sphere is rendered as 90° cube faces, each a rotation of the camera applied in camera space.
+// Faces are front, right, left, back, up, down
+pub fn face_rotations(layout: VrLayout) -> Vec<Matrix4<f32>> {
+    let y = Vector3::y_axis();
+    let x = Vector3::x_axis();
+    let mut faces = vec![
+        Rotation3::identity(),
+        Rotation3::from_axis_angle(&y, FRAC_PI_2),
+        Rotation3::from_axis_angle(&y, -FRAC_PI_2),
+        Rotation3::from_axis_angle(&y, PI),
+        Rotation3::from_axis_angle(&x, -FRAC_PI_2),
+        Rotation3::from_axis_angle(&x, FRAC_PI_2),
+    ];
+    // Nothing behind the camera shows up in VR180
+    if layout == VrLayout::Vr180 {
+        faces.remove(3);
+    }
+    faces.iter().map(Rotation3::to_homogeneous).collect()
+}
+
+impl Vr {
+    // Size of one eye's equirect image
+    pub fn eye_size(&self) -> (u32, u32) {
+        match self.layout {
+            VrLayout::Vr180 => (self.resolution, self.resolution),
+            VrLayout::Vr360 => (self.resolution * 2, self.resolution),
+        }
+    }
+
+    // Side of each square cube face. A face spans 90°, half an eye's height, so this samples the sphere at
+    // twice the equirect image's density and the bilinear resampling doesn't soften it
+    pub fn face_size(&self) -> u32 {
+        self.resolution
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        let (width, height) = self.eye_size();
+        match self.layout {
+            VrLayout::Vr180 => (width * 2, height),
+            VrLayout::Vr360 => (width, height * 2),
+        }
+    }
+
+    fn longitude_span(&self) -> f32 {
+        match self.layout {
+            VrLayout::Vr180 => PI,
+            VrLayout::Vr360 => 2.0 * PI,
+        }
+    }
+
+    // Resample one eye's cube faces (rendered with face_rotations) into its equirect image
+    pub fn resample(
+        &self,
+        rotations: &[Matrix4<f32>],
+        faces: &[ImageBuffer<Rgba<u8>, Vec<u8>>],
+    ) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+        let eye_size = self.eye_size();
+        ImageBuffer::from_fn(eye_size.0, eye_size.1, |x, y| {
+            let direction = equirect::direction(x, y, eye_size, self.longitude_span());
+            // The face a direction falls in is the one it's most aligned with, i.e. the one it hits closest to -z
+            let (rotation, face) = rotations
+                .iter()
+                .zip(faces)
+                .map(|(rotation, face)| (rotation.transform_vector(&direction), face))
+                .min_by(|(a, _), (b, _)| a.z.total_cmp(&b.z))
+                .unwrap();
+            let u = rotation.x / -rotation.z;
+            let v = rotation.y / -rotation.z;
+            imageops::sample_bilinear(face, (u + 1.0) / 2.0, (1.0 - v) / 2.0)
+                .unwrap_or(Rgba([0, 0, 0, 0]))
+        })
+    }
+
+    // Put the two eyes in the layout's arrangement
+    pub fn compose(
+        &self,
+        left: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+        right: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    ) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+        let (width, height) = self.size();
+        let mut output = ImageBuffer::new(width, height);
+        imageops::replace(&mut output, left, 0, 0);
+        match self.layout {
+            VrLayout::Vr180 => imageops::replace(&mut output, right, left.width() as i64, 0),
+            VrLayout::Vr360 => imageops::replace(&mut output, right, 0, left.height() as i64),
+        }
+        output
+    }
+}